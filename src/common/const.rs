@@ -1 +1,11 @@
 pub const DEFAULT_SIGN_FILE_NAME: &str = "dver.sig";
+pub const TOOL_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+// SSHSIG namespace, keeps our signatures from being valid for anything else
+pub const DEFAULT_NAMESPACE: &str = "dver@uintptr.github.io";
+// namespaces used by older releases, key files signed with the first one and
+// ssh-agent with the second one
+pub const LEGACY_NAMESPACES: &[&str] = &["dverify", "hello"];
+
+// sign-file and verify-file read stdin from it
+pub const STDIN_PATH: &str = "-";
@@ -1,4 +1,14 @@
-use std::{fmt::Display, fs, os::linux::fs::MetadataExt, path::Path};
+use std::{
+    fmt::Display,
+    fs,
+    os::linux::fs::MetadataExt,
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use time::{format_description::well_known::Rfc3339, Date, Month, OffsetDateTime};
+
+use crate::error::{Error, Result};
 
 pub fn fmt_size(bytes: u64) -> String {
     const KB: f64 = 1024.0;
@@ -32,7 +42,106 @@ pub fn fmt_file_size<P: AsRef<Path>>(file_path: P) -> String {
     fmt_size(file_size)
 }
 
+pub fn fmt_timestamp(timestamp: u64) -> String {
+    let date = OffsetDateTime::from_unix_timestamp(timestamp as i64);
+
+    match date.map(|d| d.format(&Rfc3339)) {
+        Ok(Ok(v)) => v,
+        _ => format!("{timestamp}"),
+    }
+}
+
+//
+// RFC 3339, a date (midnight UTC), @<unix time> or a time from now, +90d for
+// instance (s, m, h or d)
+//
+pub fn parse_time(value: &str) -> Result<u64> {
+    let invalid = || Error::InvalidArgument(format!("invalid time {value:?}"));
+
+    if let Some(seconds) = value.strip_prefix('@') {
+        return seconds.parse().map_err(|_| invalid());
+    }
+
+    if let Some(duration) = value.strip_prefix('+') {
+        let unit = match duration.chars().last() {
+            Some('s') => 1,
+            Some('m') => 60,
+            Some('h') => 60 * 60,
+            Some('d') => 24 * 60 * 60,
+            _ => return Err(invalid()),
+        };
+
+        let count: u64 = duration[..duration.len() - 1]
+            .parse()
+            .map_err(|_| invalid())?;
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+
+        return count
+            .checked_mul(unit)
+            .and_then(|d| now.checked_add(d))
+            .ok_or_else(invalid);
+    }
+
+    let timestamp = match OffsetDateTime::parse(value, &Rfc3339) {
+        Ok(v) => v.unix_timestamp(),
+        Err(_) => {
+            let fields: Vec<&str> = value.split('-').collect();
+
+            let (year, month, day) = match fields[..] {
+                [year, month, day] if year.len() == 4 && month.len() == 2 && day.len() == 2 => {
+                    (year.parse(), month.parse::<u8>(), day.parse())
+                }
+                _ => return Err(invalid()),
+            };
+
+            let (year, month, day) = match (year, month, day) {
+                (Ok(year), Ok(month), Ok(day)) => (year, month, day),
+                _ => return Err(invalid()),
+            };
+
+            let month = Month::try_from(month).map_err(|_| invalid())?;
+            let date = Date::from_calendar_date(year, month, day).map_err(|_| invalid())?;
+
+            date.midnight().assume_utc().unix_timestamp()
+        }
+    };
+
+    u64::try_from(timestamp).map_err(|_| invalid())
+}
+
+pub fn fmt_symlinks(follow: bool) -> &'static str {
+    match follow {
+        true => "follow",
+        false => "record",
+    }
+}
+
 pub fn printkv<D: Display>(k: &str, v: D) {
     let k = format!("{k}:");
     println!("    {k:<20}{v}");
 }
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn parse_times() {
+        assert_eq!(parse_time("@1700000000").unwrap(), 1700000000);
+        assert_eq!(parse_time("2025-01-25T09:45:18Z").unwrap(), 1737798318);
+        assert_eq!(parse_time("2025-01-25T10:45:18+01:00").unwrap(), 1737798318);
+        assert_eq!(parse_time("2025-01-25").unwrap(), 1737763200);
+
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let later = parse_time("+2d").unwrap();
+        assert!(later >= now + 2 * 24 * 60 * 60 && later < now + 2 * 24 * 60 * 60 + 60);
+
+        for invalid in ["", "+", "+2w", "@x", "25/01/2025", "1969-12-31"] {
+            assert!(parse_time(invalid).is_err(), "{invalid}");
+        }
+    }
+}
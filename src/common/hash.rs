@@ -1,20 +1,27 @@
 use core::fmt;
-use std::{
-    fs::File,
-    io::{BufReader, Read},
-    path::Path,
-};
+use std::{fs::File, io::Read, path::Path};
 
+use serde_derive::{Deserialize, Serialize};
 use sha2::{Digest, Sha256, Sha512};
+use sha3::{Sha3_256, Sha3_512};
 
 const HASH_BUFFER_SIZE: usize = 1024 * 8;
+const HASH_BUFFER_MAX_SIZE: usize = 1024 * 1024;
+// below this blake3 is faster on a single thread
+const BLAKE3_RAYON_MIN_SIZE: usize = 128 * 1024;
 
 use crate::error::{Error, Result};
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum DVHashType {
     Sha256,
     Sha512,
+    #[serde(rename = "sha3-256")]
+    Sha3_256,
+    #[serde(rename = "sha3-512")]
+    Sha3_512,
+    Blake3,
 }
 
 impl fmt::Display for DVHashType {
@@ -22,6 +29,9 @@ impl fmt::Display for DVHashType {
         match self {
             DVHashType::Sha256 => write!(f, "sha256"),
             DVHashType::Sha512 => write!(f, "sha512"),
+            DVHashType::Sha3_256 => write!(f, "sha3-256"),
+            DVHashType::Sha3_512 => write!(f, "sha3-512"),
+            DVHashType::Blake3 => write!(f, "blake3"),
         }
     }
 }
@@ -33,57 +43,117 @@ impl std::str::FromStr for DVHashType {
         match s.to_lowercase().as_str() {
             "sha256" => Ok(DVHashType::Sha256),
             "sha512" => Ok(DVHashType::Sha512),
+            "sha3-256" => Ok(DVHashType::Sha3_256),
+            "sha3-512" => Ok(DVHashType::Sha3_512),
+            "blake3" => Ok(DVHashType::Blake3),
             _ => Err(Error::UnknownHashType),
         }
     }
 }
 
-fn sha_file<T: Digest, P: AsRef<Path>>(file_path: P) -> Result<Vec<u8>> {
-    let fd = File::open(file_path)?;
-
-    let mut buffer: [u8; HASH_BUFFER_SIZE] = [0; HASH_BUFFER_SIZE];
-    let mut rdr = BufReader::new(fd);
+//
+// blake3 doesn't share the Digest trait of the sha crates, every algorithm is
+// driven through this instead
+//
+pub enum DVHasher {
+    Sha256(Sha256),
+    Sha512(Sha512),
+    Sha3_256(Sha3_256),
+    Sha3_512(Sha3_512),
+    Blake3(Box<blake3::Hasher>),
+}
 
-    let mut hash = T::new();
+impl DVHasher {
+    pub fn new(hash_type: DVHashType) -> DVHasher {
+        match hash_type {
+            DVHashType::Sha256 => DVHasher::Sha256(Sha256::new()),
+            DVHashType::Sha512 => DVHasher::Sha512(Sha512::new()),
+            DVHashType::Sha3_256 => DVHasher::Sha3_256(Sha3_256::new()),
+            DVHashType::Sha3_512 => DVHasher::Sha3_512(Sha3_512::new()),
+            DVHashType::Blake3 => DVHasher::Blake3(Box::default()),
+        }
+    }
 
-    loop {
-        let len = rdr.read(&mut buffer)?;
+    pub fn update<D: AsRef<[u8]>>(&mut self, data: D) {
+        let data = data.as_ref();
 
-        if 0 == len {
-            break; // EOF
+        match self {
+            DVHasher::Sha256(h) => h.update(data),
+            DVHasher::Sha512(h) => h.update(data),
+            DVHasher::Sha3_256(h) => h.update(data),
+            DVHasher::Sha3_512(h) => h.update(data),
+            // large chunks are split across the thread pool
+            DVHasher::Blake3(h) if data.len() >= BLAKE3_RAYON_MIN_SIZE => {
+                h.update_rayon(data);
+            }
+            DVHasher::Blake3(h) => {
+                h.update(data);
+            }
         }
-
-        hash.update(&buffer[..len]);
     }
 
-    let digest = hash.finalize();
-
-    Ok(digest.to_vec())
+    pub fn finalize(self) -> Vec<u8> {
+        match self {
+            DVHasher::Sha256(h) => h.finalize().to_vec(),
+            DVHasher::Sha512(h) => h.finalize().to_vec(),
+            DVHasher::Sha3_256(h) => h.finalize().to_vec(),
+            DVHasher::Sha3_512(h) => h.finalize().to_vec(),
+            DVHasher::Blake3(h) => h.finalize().as_bytes().to_vec(),
+        }
+    }
 }
 
-fn sha_data<T: Digest>(data: &[u8]) -> Vec<u8> {
-    let mut hash = T::new();
-
-    hash.update(data);
-
-    hash.finalize().to_vec()
+//
+// small files are read in one go, large ones in 1 MiB chunks. The buffer
+// belongs to the call, files are hashed on several threads
+//
+fn buffer_size(file_len: u64) -> usize {
+    let file_len = usize::try_from(file_len).unwrap_or(HASH_BUFFER_MAX_SIZE);
+
+    // one more byte so a file read in one go hits EOF on the second read
+    file_len
+        .saturating_add(1)
+        .clamp(HASH_BUFFER_SIZE, HASH_BUFFER_MAX_SIZE)
 }
 
 ////////////////////////////////////////////////////////////////////////////////
 /// PUBLIC
 ////////////////////////////////////////////////////////////////////////////////
 pub fn hash_file<P: AsRef<Path>>(file_path: P, hash_type: DVHashType) -> Result<Vec<u8>> {
-    match hash_type {
-        DVHashType::Sha256 => sha_file::<Sha256, _>(file_path),
-        DVHashType::Sha512 => sha_file::<Sha512, _>(file_path),
+    let mut fd = File::open(file_path)?;
+
+    let len = fd.metadata()?.len();
+
+    hash_reader(&mut fd, len, hash_type)
+}
+
+//
+// len is only a hint for the buffer size, everything up to EOF is hashed
+//
+pub fn hash_reader<R: Read>(reader: &mut R, len: u64, hash_type: DVHashType) -> Result<Vec<u8>> {
+    let mut buffer = vec![0; buffer_size(len)];
+
+    let mut hash = DVHasher::new(hash_type);
+
+    loop {
+        let len = reader.read(&mut buffer)?;
+
+        if 0 == len {
+            break; // EOF
+        }
+
+        hash.update(&buffer[..len]);
     }
+
+    Ok(hash.finalize())
 }
 
 pub fn hash_data(data: &[u8], hash_type: DVHashType) -> Vec<u8> {
-    match hash_type {
-        DVHashType::Sha256 => sha_data::<Sha256>(data),
-        DVHashType::Sha512 => sha_data::<Sha512>(data),
-    }
+    let mut hash = DVHasher::new(hash_type);
+
+    hash.update(data);
+
+    hash.finalize()
 }
 
 pub fn hash_string(data: &str, hash_type: DVHashType) -> Vec<u8> {
@@ -93,16 +163,67 @@ pub fn hash_string(data: &str, hash_type: DVHashType) -> Vec<u8> {
 #[cfg(test)]
 mod tests {
 
-    use super::hash_file;
-    use crate::common::hash::{hash_data, hash_string};
+    use std::fs;
+
+    use super::{hash_file, DVHashType};
+    use crate::common::hash::{hash_data, hash_string, HASH_BUFFER_MAX_SIZE};
+
+    const HASH_TYPES: &[DVHashType] = &[
+        DVHashType::Sha256,
+        DVHashType::Sha512,
+        DVHashType::Sha3_256,
+        DVHashType::Sha3_512,
+        DVHashType::Blake3,
+    ];
 
     #[test]
     fn test_hash() {
         let res = hash_file("/path/to/bleh", super::DVHashType::Sha256);
         assert!(res.is_err());
 
-        let res_str = hash_string(&String::new(), super::DVHashType::Sha256);
-        let res_data = hash_data(&vec![], super::DVHashType::Sha256);
+        let res_str = hash_string("", super::DVHashType::Sha256);
+        let res_data = hash_data(&[], super::DVHashType::Sha256);
         assert_eq!(res_str, res_data);
     }
+
+    #[test]
+    fn test_hash_buffer_sizes() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let file = tmp_dir.path().join("data");
+
+        let max = HASH_BUFFER_MAX_SIZE;
+
+        for len in [0, 1, 8191, 8192, 8193, max, 3 * max + 7] {
+            let data: Vec<u8> = (0..len).map(|i| i as u8).collect();
+            fs::write(&file, &data).unwrap();
+
+            for hash_type in HASH_TYPES {
+                let file_hash = hash_file(&file, *hash_type).unwrap();
+                assert_eq!(file_hash, hash_data(&data, *hash_type), "{hash_type} {len}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_hash_types() {
+        let expected = [
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad",
+            "ddaf35a193617abacc417349ae20413112e6fa4e89a97ea20a9eeee64b55d39a\
+             2192992a274fc1a836ba3c23a3feebbd454d4423643ce80e2a9ac94fa54ca49f",
+            "3a985da74fe225b2045c172d6bd390bd855f086e3e9d525b46bfe24511431532",
+            "b751850b1a57168a5693cd924b6b096e08f621827444f70d884f5d0240d2712e\
+             10e116e9192af3c91a7ec57647e3934057340b4cf408d5a56592f8274eec53f0",
+            "6437b3ac38465133ffb63b75273a8db548c558465d79db03fd359c6cd5bd9d85",
+        ];
+
+        for (hash_type, expected) in HASH_TYPES.iter().zip(expected) {
+            assert_eq!(hex::encode(hash_string("abc", *hash_type)), expected);
+
+            let name = hash_type.to_string();
+            assert_eq!(name.parse::<DVHashType>().unwrap(), *hash_type);
+
+            let json = serde_json::to_string(hash_type).unwrap();
+            assert_eq!(json, format!("\"{name}\""));
+        }
+    }
 }
@@ -1,4 +1,8 @@
 pub mod r#const;
 pub mod fmt;
 pub mod hash;
+pub mod report;
 pub mod serializer;
+
+#[cfg(test)]
+pub mod testing;
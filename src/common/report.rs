@@ -0,0 +1,174 @@
+use core::fmt;
+use std::fmt::Display;
+
+use serde::Serialize;
+use serde_json::{Map, Value};
+
+use crate::{
+    common::fmt::printkv,
+    error::{Error, Result},
+};
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum DVOutputFormat {
+    Text,
+    Json,
+}
+
+impl std::str::FromStr for DVOutputFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "text" => Ok(DVOutputFormat::Text),
+            "json" => Ok(DVOutputFormat::Json),
+            _ => Err(Error::UnknownOutputFormat),
+        }
+    }
+}
+
+impl fmt::Display for DVOutputFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DVOutputFormat::Text => write!(f, "text"),
+            DVOutputFormat::Json => write!(f, "json"),
+        }
+    }
+}
+
+//
+// text output is printed as we go, json output is collected and printed as a
+// single document once the command is done
+//
+#[derive(Debug)]
+pub struct Report {
+    format: DVOutputFormat,
+    fields: Map<String, Value>,
+}
+
+fn json_key(key: &str) -> String {
+    key.to_lowercase().replace(' ', "_")
+}
+
+impl Report {
+    pub fn new(format: DVOutputFormat, title: &str) -> Report {
+        if let DVOutputFormat::Text = format {
+            println!("{title}:");
+        }
+
+        Report {
+            format,
+            fields: Map::new(),
+        }
+    }
+
+    pub fn add<D: Display>(&mut self, key: &str, value: D) {
+        match self.format {
+            DVOutputFormat::Text => printkv(key, value),
+            DVOutputFormat::Json => {
+                let value = Value::String(value.to_string());
+                self.fields.insert(json_key(key), value);
+            }
+        }
+    }
+
+    pub fn add_list<D: Display>(&mut self, key: &str, values: &[D]) {
+        match self.format {
+            DVOutputFormat::Text => {
+                let values: Vec<String> = values.iter().map(|v| v.to_string()).collect();
+                printkv(key, format!("{values:?}"))
+            }
+            DVOutputFormat::Json => {
+                let values = values.iter().map(|v| Value::String(v.to_string()));
+                self.fields
+                    .insert(json_key(key), Value::Array(values.collect()));
+            }
+        }
+    }
+
+    //
+    // one line per item labelled by the closure in text, a list of objects in
+    // json
+    //
+    pub fn add_items<T, F>(&mut self, key: &str, items: &[T], label: F) -> Result<()>
+    where
+        T: Serialize + Display,
+        F: Fn(&T) -> String,
+    {
+        match self.format {
+            DVOutputFormat::Text => {
+                for item in items {
+                    printkv(&label(item), item);
+                }
+            }
+            DVOutputFormat::Json => {
+                let value = serde_json::to_value(items)?;
+                self.fields.insert(json_key(key), value);
+            }
+        }
+
+        Ok(())
+    }
+
+    //
+    // a report of its own for a nested verification, printed under its title
+    // in text and collected as a list of objects in json
+    //
+    pub fn section(&self, title: &str) -> Report {
+        Report::new(self.format, title)
+    }
+
+    pub fn add_section(&mut self, key: &str, section: Report) {
+        if let DVOutputFormat::Text = self.format {
+            return;
+        }
+
+        let sections = self
+            .fields
+            .entry(json_key(key))
+            .or_insert_with(|| Value::Array(vec![]));
+
+        if let Value::Array(sections) = sections {
+            sections.push(Value::Object(section.fields));
+        }
+    }
+
+    pub fn finish<T>(mut self, ret: &Result<T>) {
+        if let DVOutputFormat::Text = self.format {
+            return;
+        }
+
+        let result = match ret {
+            Ok(_) => "success",
+            Err(_) => "failure",
+        };
+
+        self.fields.insert("result".into(), result.into());
+
+        if let Err(e) = ret {
+            self.fields.insert("error".into(), e.to_string().into());
+        }
+
+        match serde_json::to_string_pretty(&self.fields) {
+            Ok(v) => println!("{v}"),
+            Err(e) => eprintln!("unable to serialize the report: {e}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn report_json() {
+        let mut report = Report::new(DVOutputFormat::Json, "Verifying");
+
+        report.add("Signature File", "/tmp/product/dver.sig");
+        report.add_list("Exclude", &["*.pyc"]);
+
+        assert_eq!(report.fields["signature_file"], "/tmp/product/dver.sig");
+        assert_eq!(report.fields["exclude"][0], "*.pyc");
+    }
+}
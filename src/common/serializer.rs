@@ -8,6 +8,41 @@ where
     serializer.serialize_str(&hex::encode(bytes))
 }
 
+pub fn hex_deserializer<'de, D>(deserializer: D) -> Result<Vec<u8>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let s: &str = Deserialize::deserialize(deserializer)?;
+
+    match hex::decode(s) {
+        Ok(v) => Ok(v),
+        Err(e) => Err(serde::de::Error::custom(e)),
+    }
+}
+
+pub fn hex_option_serializer<S>(bytes: &Option<Vec<u8>>, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    match bytes {
+        Some(bytes) => serializer.serialize_some(&hex::encode(bytes)),
+        None => serializer.serialize_none(),
+    }
+}
+
+pub fn hex_option_deserializer<'de, D>(deserializer: D) -> Result<Option<Vec<u8>>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let s: Option<&str> = Deserialize::deserialize(deserializer)?;
+
+    match s.map(hex::decode) {
+        Some(Ok(v)) => Ok(Some(v)),
+        Some(Err(e)) => Err(serde::de::Error::custom(e)),
+        None => Ok(None),
+    }
+}
+
 pub fn base64_serializer<S>(bytes: &Vec<u8>, serializer: S) -> Result<S::Ok, S::Error>
 where
     S: Serializer,
@@ -26,3 +61,21 @@ where
         Err(e) => Err(serde::de::Error::custom(e)),
     }
 }
+
+pub fn base64_list_serializer<S>(list: &[Vec<u8>], serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    serializer.collect_seq(list.iter().map(|bytes| BASE64_STANDARD.encode(bytes)))
+}
+
+pub fn base64_list_deserializer<'de, D>(deserializer: D) -> Result<Vec<Vec<u8>>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let list: Vec<String> = Deserialize::deserialize(deserializer)?;
+
+    list.iter()
+        .map(|s| BASE64_STANDARD.decode(s).map_err(serde::de::Error::custom))
+        .collect()
+}
@@ -0,0 +1,798 @@
+use core::fmt;
+use std::{
+    collections::BTreeMap,
+    ffi::OsString,
+    fs::File,
+    io::{BufReader, Read, Seek},
+    os::unix::ffi::OsStringExt,
+    path::{Component, Path, PathBuf},
+};
+
+use flate2::read::MultiGzDecoder;
+
+use crate::{
+    common::{
+        hash::{hash_reader, DVHashType},
+        r#const::DEFAULT_SIGN_FILE_NAME,
+    },
+    error::{Error, Result},
+};
+
+use super::{
+    attributes::{DVAttribute, WalkerAttributes, PERMISSIONS_MASK},
+    special::DVSpecialKind,
+    xattrs::WalkerXattr,
+};
+
+const PAX_XATTR_PREFIX: &str = "SCHILY.xattr.";
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum DVArchiveType {
+    Tar,
+    TarGz,
+    TarZst,
+    Zip,
+}
+
+const ARCHIVE_EXTENSIONS: &[(&str, DVArchiveType)] = &[
+    (".tar", DVArchiveType::Tar),
+    (".tar.gz", DVArchiveType::TarGz),
+    (".tgz", DVArchiveType::TarGz),
+    (".tar.zst", DVArchiveType::TarZst),
+    (".tzst", DVArchiveType::TarZst),
+    (".zip", DVArchiveType::Zip),
+];
+
+impl fmt::Display for DVArchiveType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DVArchiveType::Tar => write!(f, "tar"),
+            DVArchiveType::TarGz => write!(f, "tar.gz"),
+            DVArchiveType::TarZst => write!(f, "tar.zst"),
+            DVArchiveType::Zip => write!(f, "zip"),
+        }
+    }
+}
+
+impl DVArchiveType {
+    //
+    // detected from the extension, along with the name of the directory the
+    // archive unpacks to
+    //
+    pub fn from_path(path: &Path) -> Option<(DVArchiveType, String)> {
+        let name = path.file_name()?.to_str()?;
+
+        ARCHIVE_EXTENSIONS
+            .iter()
+            .find_map(|(ext, archive_type)| match name.strip_suffix(ext) {
+                Some(stem) if !stem.is_empty() => Some((*archive_type, stem.to_string())),
+                _ => None,
+            })
+    }
+}
+
+//
+// None for a directory
+//
+pub fn archive_type(path: &Path) -> Option<DVArchiveType> {
+    match path.is_file() {
+        true => DVArchiveType::from_path(path).map(|(archive_type, _)| archive_type),
+        false => None,
+    }
+}
+
+pub fn is_archive(path: &Path) -> bool {
+    archive_type(path).is_some()
+}
+
+//
+// an archive can't hold its own signature, it sits next to it
+//
+pub fn default_signature_file(directory: &Path) -> PathBuf {
+    if !is_archive(directory) {
+        return directory.join(DEFAULT_SIGN_FILE_NAME);
+    }
+
+    let mut name = OsString::from(directory.as_os_str());
+    name.push(".");
+    name.push(DEFAULT_SIGN_FILE_NAME);
+    name.into()
+}
+
+//
+// entries can't point outside of the archive, "./" is the archive itself
+//
+fn normalize_entry(path: &Path) -> Result<PathBuf> {
+    let mut normalized = PathBuf::new();
+
+    for component in path.components() {
+        match component {
+            Component::Normal(v) => normalized.push(v),
+            Component::CurDir => {}
+            _ => {
+                let err = format!("{:?} points outside of the archive", path);
+                return Err(Error::InvalidPath(err));
+            }
+        }
+    }
+
+    Ok(normalized)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ArchiveEntryKind {
+    // hash of the content
+    File(Vec<u8>),
+    Directory,
+    Symlink(PathBuf),
+    Special(DVSpecialKind, Option<u64>),
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ArchiveMetadata {
+    pub mode: u32,
+    pub uid: Option<u32>,
+    pub gid: Option<u32>,
+    pub owner: Option<String>,
+    pub group: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ArchiveEntry {
+    pub kind: ArchiveEntryKind,
+    // None for directories only implied by the paths below them
+    pub metadata: Option<ArchiveMetadata>,
+    pub xattrs: Vec<WalkerXattr>,
+    // shared by the names of a tar hardlink
+    pub inode: Option<u64>,
+}
+
+impl ArchiveEntry {
+    fn implied() -> ArchiveEntry {
+        ArchiveEntry {
+            kind: ArchiveEntryKind::Directory,
+            metadata: None,
+            xattrs: vec![],
+            inode: None,
+        }
+    }
+
+    pub fn mode(&self) -> u32 {
+        self.metadata.as_ref().map(|m| m.mode).unwrap_or_default()
+    }
+
+    //
+    // what the archive didn't record can't be compared with a directory, a
+    // name is only missing when the id has none
+    //
+    pub fn attributes(&self, path: &Path, attributes: &[DVAttribute]) -> Result<WalkerAttributes> {
+        let mut a = WalkerAttributes::default();
+
+        for attribute in attributes {
+            let metadata = match &self.metadata {
+                Some(v) => v,
+                None => {
+                    let err = format!("the archive doesn't record the {attribute} of {:?}", path);
+                    return Err(Error::InvalidArgument(err));
+                }
+            };
+
+            let recorded = match attribute {
+                DVAttribute::Mode => {
+                    a.permissions = Some(metadata.mode & PERMISSIONS_MASK);
+                    true
+                }
+                DVAttribute::Uid => {
+                    a.uid = metadata.uid;
+                    a.uid.is_some()
+                }
+                DVAttribute::Gid => {
+                    a.gid = metadata.gid;
+                    a.gid.is_some()
+                }
+                DVAttribute::Owner => {
+                    a.owner = metadata.owner.clone();
+                    metadata.uid.is_some()
+                }
+                DVAttribute::Group => {
+                    a.group = metadata.group.clone();
+                    metadata.gid.is_some()
+                }
+            };
+
+            if !recorded {
+                let err = format!("the archive doesn't record the {attribute} of {:?}", path);
+                return Err(Error::InvalidArgument(err));
+            }
+        }
+
+        Ok(a)
+    }
+}
+
+//
+// the entries of an archive by path, relative to the archive. Nothing is
+// extracted, file content is hashed while it's read
+//
+#[derive(Debug)]
+pub struct ArchiveTree {
+    entries: BTreeMap<PathBuf, ArchiveEntry>,
+    // zip doesn't record extended attributes
+    pub xattrs: bool,
+    inodes: u64,
+}
+
+impl ArchiveTree {
+    //
+    // name is the directory the archive unpacks to, an archive holding only
+    // that directory is read as its content
+    //
+    pub fn read<P: AsRef<Path>>(
+        archive: P,
+        archive_type: DVArchiveType,
+        hash_type: DVHashType,
+        name: &str,
+    ) -> Result<ArchiveTree> {
+        let fd = BufReader::new(File::open(archive)?);
+
+        let mut tree = ArchiveTree {
+            entries: BTreeMap::new(),
+            xattrs: archive_type != DVArchiveType::Zip,
+            inodes: 0,
+        };
+
+        tree.entries.insert(PathBuf::new(), ArchiveEntry::implied());
+
+        match archive_type {
+            DVArchiveType::Tar => tree.read_tar(fd, hash_type)?,
+            DVArchiveType::TarGz => tree.read_tar(MultiGzDecoder::new(fd), hash_type)?,
+            DVArchiveType::TarZst => tree.read_tar(zstd::Decoder::with_buffer(fd)?, hash_type)?,
+            DVArchiveType::Zip => tree.read_zip(fd, hash_type)?,
+        }
+
+        tree.strip_top_level(Path::new(name));
+
+        Ok(tree)
+    }
+
+    //
+    // "tar czf product.tar.gz product/" archives the directory itself rather
+    // than its content
+    //
+    fn strip_top_level(&mut self, name: &Path) {
+        let children: Vec<_> = self.children(Path::new("")).collect();
+
+        match children[..] {
+            [(path, entry)] if path == name && entry.kind == ArchiveEntryKind::Directory => {}
+            _ => return,
+        }
+
+        let entries = std::mem::take(&mut self.entries);
+
+        self.entries = entries
+            .into_iter()
+            .filter_map(|(path, entry)| {
+                let path = path.strip_prefix(name).ok()?.to_path_buf();
+                Some((path, entry))
+            })
+            .collect();
+    }
+
+    pub fn get(&self, path: &Path) -> Option<&ArchiveEntry> {
+        self.entries.get(path)
+    }
+
+    //
+    // the entries right below dir, in name order
+    //
+    pub fn children<'a>(
+        &'a self,
+        dir: &'a Path,
+    ) -> impl Iterator<Item = (&'a PathBuf, &'a ArchiveEntry)> {
+        self.entries
+            .range(dir.to_path_buf()..)
+            .skip(1)
+            .take_while(move |(path, _)| path.starts_with(dir))
+            .filter(move |(path, _)| path.parent() == Some(dir))
+    }
+
+    //
+    // the parents of an entry are directories even when the archive doesn't
+    // list them. Like tar, a later entry replaces an earlier one
+    //
+    fn insert(&mut self, path: PathBuf, entry: ArchiveEntry) -> Result<()> {
+        if path.as_os_str().is_empty() && entry.kind != ArchiveEntryKind::Directory {
+            return Err(Error::InvalidPath(
+                "the archive itself isn't a directory".into(),
+            ));
+        }
+
+        for parent in path.ancestors().skip(1) {
+            match self.entries.get(parent) {
+                Some(e) if e.kind != ArchiveEntryKind::Directory => {
+                    let err = format!("{:?} is below {:?} which isn't a directory", path, parent);
+                    return Err(Error::InvalidPath(err));
+                }
+                Some(_) => {}
+                None => {
+                    self.entries.insert(parent.into(), ArchiveEntry::implied());
+                }
+            }
+        }
+
+        if let Some(e) = self.entries.get(&path)
+            && e.kind == ArchiveEntryKind::Directory
+            && entry.kind != ArchiveEntryKind::Directory
+            && self.children(&path).next().is_some()
+        {
+            let err = format!("{:?} replaces a directory", path);
+            return Err(Error::InvalidPath(err));
+        }
+
+        self.entries.insert(path, entry);
+
+        Ok(())
+    }
+
+    //
+    // a hardlink is another name of an earlier entry
+    //
+    fn link(&mut self, path: PathBuf, target: PathBuf) -> Result<()> {
+        let inodes = &mut self.inodes;
+
+        let entry = match self.entries.get_mut(&target) {
+            Some(e) if matches!(e.kind, ArchiveEntryKind::File(_)) => {
+                if e.inode.is_none() {
+                    *inodes += 1;
+                    e.inode = Some(*inodes);
+                }
+                e.clone()
+            }
+            _ => {
+                let err = format!(
+                    "{:?} links to {:?} which isn't a file of the archive",
+                    path, target
+                );
+                return Err(Error::InvalidPath(err));
+            }
+        };
+
+        self.insert(path, entry)
+    }
+
+    fn read_tar<R: Read>(&mut self, reader: R, hash_type: DVHashType) -> Result<()> {
+        let mut archive = tar::Archive::new(reader);
+        let mut links = Vec::new();
+
+        for entry in archive.entries()? {
+            let mut entry = entry?;
+
+            let path = normalize_entry(&entry.path()?)?;
+
+            let header = entry.header();
+            let entry_type = header.entry_type();
+            let size = header.size()?;
+
+            let mut metadata = ArchiveMetadata {
+                mode: header.mode()?,
+                uid: u32::try_from(header.uid()?).ok(),
+                gid: u32::try_from(header.gid()?).ok(),
+                owner: header
+                    .username()?
+                    .filter(|v| !v.is_empty())
+                    .map(String::from),
+                group: header
+                    .groupname()?
+                    .filter(|v| !v.is_empty())
+                    .map(String::from),
+            };
+
+            // the fields are empty for anything but a device node
+            let device = match entry_type.is_character_special() || entry_type.is_block_special() {
+                true => match (header.device_major()?, header.device_minor()?) {
+                    (Some(major), Some(minor)) => Some(libc::makedev(major, minor)),
+                    _ => None,
+                },
+                false => None,
+            };
+
+            let link_name = entry.link_name()?.map(|v| v.into_owned());
+
+            let mut xattrs = Vec::new();
+
+            // pax records override the header
+            if let Some(extensions) = entry.pax_extensions()? {
+                for extension in extensions {
+                    let extension = extension?;
+                    let value = extension.value_bytes();
+
+                    match extension.key()? {
+                        "uid" => metadata.uid = std::str::from_utf8(value)?.parse().ok(),
+                        "gid" => metadata.gid = std::str::from_utf8(value)?.parse().ok(),
+                        "uname" => metadata.owner = Some(String::from_utf8(value.to_vec())?),
+                        "gname" => metadata.group = Some(String::from_utf8(value.to_vec())?),
+                        key => {
+                            if let Some(name) = key.strip_prefix(PAX_XATTR_PREFIX) {
+                                xattrs.push(WalkerXattr {
+                                    name: name.to_string(),
+                                    value: value.to_vec(),
+                                });
+                            }
+                        }
+                    }
+                }
+            }
+
+            xattrs.sort_by(|a, b| a.name.cmp(&b.name));
+
+            let kind = match entry_type {
+                t if t.is_file() || t.is_contiguous() => {
+                    ArchiveEntryKind::File(hash_reader(&mut entry, size, hash_type)?)
+                }
+                t if t.is_dir() => ArchiveEntryKind::Directory,
+                t if t.is_symlink() => match link_name {
+                    Some(v) => ArchiveEntryKind::Symlink(v),
+                    None => {
+                        let err = format!("{:?} is a link without a target", path);
+                        return Err(Error::InvalidPath(err));
+                    }
+                },
+                t if t.is_hard_link() => match link_name {
+                    Some(v) => {
+                        links.push((path, normalize_entry(&v)?));
+                        continue;
+                    }
+                    None => {
+                        let err = format!("{:?} is a link without a target", path);
+                        return Err(Error::InvalidPath(err));
+                    }
+                },
+                t if t.is_fifo() => ArchiveEntryKind::Special(DVSpecialKind::Fifo, None),
+                t if t.is_character_special() => {
+                    ArchiveEntryKind::Special(DVSpecialKind::Char, device)
+                }
+                t if t.is_block_special() => {
+                    ArchiveEntryKind::Special(DVSpecialKind::Block, device)
+                }
+                t if t.is_pax_global_extensions() => continue,
+                t => {
+                    let err = format!("{:?} is an unsupported {:?} entry", path, t);
+                    return Err(Error::InvalidPath(err));
+                }
+            };
+
+            let entry = ArchiveEntry {
+                kind,
+                metadata: Some(metadata),
+                xattrs,
+                inode: None,
+            };
+
+            self.insert(path, entry)?;
+        }
+
+        // the target of a link is always archived before it
+        for (path, target) in links {
+            self.link(path, target)?;
+        }
+
+        Ok(())
+    }
+
+    fn read_zip<R: Read + Seek>(&mut self, reader: R, hash_type: DVHashType) -> Result<()> {
+        let mut archive = zip::ZipArchive::new(reader)?;
+
+        for i in 0..archive.len() {
+            let mut file = archive.by_index(i)?;
+
+            let path = normalize_entry(Path::new(&*file.name()?))?;
+
+            // archives made on windows don't have any
+            let metadata = file.unix_mode().map(|mode| ArchiveMetadata {
+                mode,
+                ..Default::default()
+            });
+
+            let kind = if file.is_dir() {
+                ArchiveEntryKind::Directory
+            } else if file.is_symlink() {
+                let mut target = Vec::new();
+                file.read_to_end(&mut target)?;
+                ArchiveEntryKind::Symlink(OsString::from_vec(target).into())
+            } else {
+                let size = file.size();
+                ArchiveEntryKind::File(hash_reader(&mut file, size, hash_type)?)
+            };
+
+            let entry = ArchiveEntry {
+                kind,
+                metadata,
+                xattrs: vec![],
+                inode: None,
+            };
+
+            self.insert(path, entry)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::{
+        fs,
+        io::Write,
+        os::unix::fs::{symlink, PermissionsExt},
+    };
+
+    use flate2::{write::GzEncoder, Compression};
+    use walkdir::WalkDir;
+    use zip::{write::SimpleFileOptions, ZipWriter};
+
+    use crate::{
+        common::testing::{write_test_gnu_tar, write_test_tar, write_test_tree},
+        directory::{
+            attributes::DVAttribute,
+            special::DVSpecialPolicy,
+            walker::{Walker, WalkerHeader},
+        },
+    };
+
+    use base64::{prelude::BASE64_STANDARD, Engine};
+
+    use super::*;
+
+    fn manifest(path: &Path, header: &WalkerHeader) -> Result<String> {
+        let mut walker = Walker::with_header(header.clone());
+        walker.walk(path, DVHashType::Sha256)?;
+        walker.encode()
+    }
+
+    fn write_product(product: &Path) {
+        write_test_tree(product);
+
+        let www = product.join("www");
+        fs::set_permissions(
+            www.join("js").join("app.js"),
+            fs::Permissions::from_mode(0o755),
+        )
+        .unwrap();
+        fs::write(product.join("server.pyc"), "compiled").unwrap();
+        fs::write(product.join(DEFAULT_SIGN_FILE_NAME), "signature").unwrap();
+        fs::create_dir(product.join("empty")).unwrap();
+        symlink("www", product.join("site")).unwrap();
+
+        xattr::set(product.join("server.py"), "user.dver.label", b"bin_t").unwrap();
+    }
+
+    fn write_zip(product: &Path, archive: &Path) {
+        let mut zip = ZipWriter::new(fs::File::create(archive).unwrap());
+
+        for entry in WalkDir::new(product).min_depth(1).sort_by_file_name() {
+            let entry = entry.unwrap();
+            let name = entry
+                .path()
+                .strip_prefix(product)
+                .unwrap()
+                .to_str()
+                .unwrap();
+
+            let metadata = entry.path().symlink_metadata().unwrap();
+            let options =
+                SimpleFileOptions::default().unix_permissions(metadata.permissions().mode());
+
+            if metadata.is_dir() {
+                zip.add_directory(name, options).unwrap();
+            } else if metadata.is_symlink() {
+                let target = fs::read_link(entry.path()).unwrap();
+                zip.add_symlink(name, target.to_str().unwrap(), options)
+                    .unwrap();
+            } else {
+                zip.start_file(name, options).unwrap();
+                zip.write_all(&fs::read(entry.path()).unwrap()).unwrap();
+            }
+        }
+
+        zip.finish().unwrap();
+    }
+
+    #[test]
+    fn archive_types() {
+        let types = [
+            ("product.tar", Some(DVArchiveType::Tar)),
+            ("product.tar.gz", Some(DVArchiveType::TarGz)),
+            ("product.tgz", Some(DVArchiveType::TarGz)),
+            ("product.tar.zst", Some(DVArchiveType::TarZst)),
+            ("product.zip", Some(DVArchiveType::Zip)),
+            ("product.gz", None),
+            (".tar", None),
+        ];
+
+        for (name, expected) in types {
+            let found = DVArchiveType::from_path(Path::new(name));
+            assert_eq!(found.as_ref().map(|v| v.0), expected, "{name}");
+
+            if let Some((_, stem)) = found {
+                assert_eq!(stem, "product");
+            }
+        }
+
+        let sig = default_signature_file(Path::new("/missing/product.tar"));
+        assert_eq!(
+            sig,
+            Path::new("/missing/product.tar").join(DEFAULT_SIGN_FILE_NAME)
+        );
+    }
+
+    #[test]
+    fn archive_tar() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let product = tmp_dir.path().join("product");
+        write_product(&product);
+        fs::hard_link(product.join("server.py"), product.join("main.py")).unwrap();
+
+        let out = tmp_dir.path().join("out");
+        fs::create_dir(&out).unwrap();
+
+        let tar = out.join("product.tar");
+
+        write_test_tar(&product, &tar, ".");
+
+        let data = fs::read(&tar).unwrap();
+
+        let mut gz = GzEncoder::new(Vec::new(), Compression::default());
+        gz.write_all(&data).unwrap();
+        fs::write(out.join("product.tar.gz"), gz.finish().unwrap()).unwrap();
+
+        fs::write(
+            out.join("product.tar.zst"),
+            zstd::encode_all(&data[..], 0).unwrap(),
+        )
+        .unwrap();
+
+        let header = WalkerHeader {
+            exclude: vec!["*.pyc".into()],
+            attributes: vec![
+                DVAttribute::Mode,
+                DVAttribute::Uid,
+                DVAttribute::Gid,
+                DVAttribute::Owner,
+                DVAttribute::Group,
+            ],
+            xattrs: vec!["user".into()],
+            special_files: DVSpecialPolicy::Record,
+            ..Default::default()
+        };
+
+        let expected = manifest(&product, &header).unwrap();
+
+        for name in ["product.tar", "product.tar.gz", "product.tar.zst"] {
+            assert_eq!(
+                manifest(&out.join(name), &header).unwrap(),
+                expected,
+                "{name}"
+            );
+        }
+
+        // the GNU format leaves the device fields empty
+        let gnu = tmp_dir.path().join("gnu");
+        fs::create_dir(&gnu).unwrap();
+
+        write_test_gnu_tar(&product, gnu.join("product.tar"));
+
+        let no_xattrs = WalkerHeader {
+            xattrs: vec![],
+            ..header.clone()
+        };
+        assert_eq!(
+            manifest(&gnu.join("product.tar"), &no_xattrs).unwrap(),
+            manifest(&product, &no_xattrs).unwrap()
+        );
+
+        let mut walker = Walker::with_header(header);
+        walker.walk(&tar, DVHashType::Sha256).unwrap();
+
+        let json =
+            String::from_utf8(BASE64_STANDARD.decode(walker.encode().unwrap()).unwrap()).unwrap();
+        assert!(json.contains("\"hardlink\":\"product/main.py\""));
+
+        assert!(json.contains("user.dver.label"));
+    }
+
+    #[test]
+    fn archive_top_level() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let product = tmp_dir.path().join("product");
+        write_product(&product);
+
+        let out = tmp_dir.path().join("out");
+        fs::create_dir(&out).unwrap();
+
+        let header = WalkerHeader {
+            exclude: vec!["*.pyc".into()],
+            attributes: vec![DVAttribute::Mode, DVAttribute::Owner],
+            ..Default::default()
+        };
+
+        // tar cf product.tar product/
+        let tar = out.join("product.tar");
+        write_test_tar(&product, &tar, "product");
+        assert_eq!(
+            manifest(&tar, &header).unwrap(),
+            manifest(&product, &header).unwrap()
+        );
+
+        let mut walker = Walker::with_header(header.clone());
+        walker.walk(&product, DVHashType::Sha256).unwrap();
+        assert!(walker
+            .diff_path(&tar, DVHashType::Sha256, Path::new("www"))
+            .unwrap()
+            .is_empty());
+
+        // any other directory is content
+        let other = out.join("other");
+        fs::create_dir(&other).unwrap();
+        let tar = other.join("product.tar");
+        write_test_tar(&product, &tar, "release");
+
+        // whose mode the archive doesn't record
+        let mut walker = Walker::with_header(WalkerHeader::default());
+        walker.walk(&tar, DVHashType::Sha256).unwrap();
+        assert_eq!(
+            walker.signature_files(),
+            [Path::new("product/release/dver.sig")]
+        );
+    }
+
+    #[test]
+    fn archive_zip() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let product = tmp_dir.path().join("product");
+        write_product(&product);
+
+        let archive = tmp_dir.path().join("product.zip");
+        write_zip(&product, &archive);
+
+        let header = WalkerHeader {
+            exclude: vec!["*.pyc".into()],
+            ..Default::default()
+        };
+
+        assert_eq!(
+            manifest(&archive, &header).unwrap(),
+            manifest(&product, &header).unwrap()
+        );
+
+        // the archive itself has no entry, its mode is unknown
+        let header = WalkerHeader {
+            attributes: vec![DVAttribute::Mode],
+            ..Default::default()
+        };
+
+        assert!(matches!(
+            manifest(&archive, &header),
+            Err(Error::InvalidArgument(_))
+        ));
+    }
+
+    #[test]
+    fn archive_escapes() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let archive = tmp_dir.path().join("product.tar");
+
+        let mut builder = tar::Builder::new(fs::File::create(&archive).unwrap());
+
+        let mut header = tar::Header::new_gnu();
+        header.as_old_mut().name[..7].copy_from_slice(b"../evil");
+        header.set_size(0);
+        header.set_entry_type(tar::EntryType::Regular);
+        header.set_cksum();
+        builder.append(&header, &[][..]).unwrap();
+        builder.finish().unwrap();
+
+        let ret = manifest(&archive, &WalkerHeader::default());
+        assert!(matches!(ret, Err(Error::InvalidPath(_))));
+    }
+}
@@ -0,0 +1,218 @@
+use core::fmt;
+use std::{
+    fs::Metadata,
+    os::unix::fs::{MetadataExt, PermissionsExt},
+};
+
+use serde_derive::{Deserialize, Serialize};
+
+use crate::error::{Error, Result};
+
+// permission bits including setuid, setgid and sticky
+pub const PERMISSIONS_MASK: u32 = 0o7777;
+
+const ATTRIBUTE_MODE: u8 = b'm';
+const ATTRIBUTE_UID: u8 = b'u';
+const ATTRIBUTE_GID: u8 = b'g';
+const ATTRIBUTE_OWNER: u8 = b'o';
+const ATTRIBUTE_GROUP: u8 = b'n';
+
+//
+// what can be recorded on top of the content. uid and gid rarely match across
+// build hosts, the names or nothing at all can be enforced instead
+//
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DVAttribute {
+    Mode,
+    Uid,
+    Gid,
+    Owner,
+    Group,
+}
+
+impl fmt::Display for DVAttribute {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DVAttribute::Mode => write!(f, "mode"),
+            DVAttribute::Uid => write!(f, "uid"),
+            DVAttribute::Gid => write!(f, "gid"),
+            DVAttribute::Owner => write!(f, "owner"),
+            DVAttribute::Group => write!(f, "group"),
+        }
+    }
+}
+
+impl std::str::FromStr for DVAttribute {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "mode" => Ok(DVAttribute::Mode),
+            "uid" => Ok(DVAttribute::Uid),
+            "gid" => Ok(DVAttribute::Gid),
+            "owner" => Ok(DVAttribute::Owner),
+            "group" => Ok(DVAttribute::Group),
+            _ => Err(Error::InvalidArgument(format!("unknown attribute {s}"))),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct WalkerAttributes {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub permissions: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub uid: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gid: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub owner: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub group: Option<String>,
+}
+
+impl WalkerAttributes {
+    // signed subtrees carry no attributes, their own manifest records them
+    pub const NONE: WalkerAttributes = WalkerAttributes {
+        permissions: None,
+        uid: None,
+        gid: None,
+        owner: None,
+        group: None,
+    };
+
+    pub fn new(metadata: &Metadata, attributes: &[DVAttribute]) -> WalkerAttributes {
+        let mut a = WalkerAttributes::default();
+
+        for attribute in attributes {
+            match attribute {
+                DVAttribute::Mode => {
+                    a.permissions = Some(metadata.permissions().mode() & PERMISSIONS_MASK)
+                }
+                DVAttribute::Uid => a.uid = Some(metadata.uid()),
+                DVAttribute::Gid => a.gid = Some(metadata.gid()),
+                // ids without a name are recorded without one
+                DVAttribute::Owner => {
+                    a.owner = uzers::get_user_by_uid(metadata.uid())
+                        .map(|u| u.name().to_string_lossy().into_owned())
+                }
+                DVAttribute::Group => {
+                    a.group = uzers::get_group_by_gid(metadata.gid())
+                        .map(|g| g.name().to_string_lossy().into_owned())
+                }
+            }
+        }
+
+        a
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == WalkerAttributes::default()
+    }
+
+    //
+    // drops what isn't part of the given list
+    //
+    pub fn retain(&mut self, attributes: &[DVAttribute]) {
+        if !attributes.contains(&DVAttribute::Mode) {
+            self.permissions = None;
+        }
+        if !attributes.contains(&DVAttribute::Uid) {
+            self.uid = None;
+        }
+        if !attributes.contains(&DVAttribute::Gid) {
+            self.gid = None;
+        }
+        if !attributes.contains(&DVAttribute::Owner) {
+            self.owner = None;
+        }
+        if !attributes.contains(&DVAttribute::Group) {
+            self.group = None;
+        }
+    }
+
+    //
+    // tagged so the directory hash tells which attributes were recorded,
+    // nothing at all when none were
+    //
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+
+        let numbers = [
+            (ATTRIBUTE_MODE, self.permissions),
+            (ATTRIBUTE_UID, self.uid),
+            (ATTRIBUTE_GID, self.gid),
+        ];
+
+        for (tag, value) in numbers {
+            if let Some(v) = value {
+                out.push(tag);
+                out.extend(v.to_be_bytes());
+            }
+        }
+
+        let names = [
+            (ATTRIBUTE_OWNER, &self.owner),
+            (ATTRIBUTE_GROUP, &self.group),
+        ];
+
+        for (tag, value) in names {
+            if let Some(v) = value {
+                out.push(tag);
+                out.extend((v.len() as u32).to_be_bytes());
+                out.extend(v.as_bytes());
+            }
+        }
+
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::fs;
+
+    use super::*;
+
+    #[test]
+    fn walker_attributes() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let file = tmp_dir.path().join("file");
+        fs::write(&file, "hello").unwrap();
+        fs::set_permissions(&file, fs::Permissions::from_mode(0o4750)).unwrap();
+
+        let metadata = fs::metadata(&file).unwrap();
+
+        let none = WalkerAttributes::new(&metadata, &[]);
+        assert!(none.is_empty());
+        assert!(none.encode().is_empty());
+
+        let all = [
+            DVAttribute::Mode,
+            DVAttribute::Uid,
+            DVAttribute::Gid,
+            DVAttribute::Owner,
+            DVAttribute::Group,
+        ];
+
+        let mut a = WalkerAttributes::new(&metadata, &all);
+        assert_eq!(a.permissions, Some(0o4750));
+        assert_eq!(a.uid, Some(metadata.uid()));
+        assert_eq!(a.gid, Some(metadata.gid()));
+
+        a.retain(&[DVAttribute::Mode]);
+        assert_eq!(
+            a.encode(),
+            [&[ATTRIBUTE_MODE][..], &0o4750u32.to_be_bytes()].concat()
+        );
+
+        for attribute in all {
+            assert_eq!(
+                attribute.to_string().parse::<DVAttribute>().unwrap(),
+                attribute
+            );
+        }
+    }
+}
@@ -0,0 +1,281 @@
+use std::{
+    collections::HashMap,
+    env,
+    fs::{self, Metadata},
+    io::Write,
+    os::unix::fs::MetadataExt,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use log::{info, warn};
+use serde_derive::{Deserialize, Serialize};
+use tempfile::NamedTempFile;
+
+use crate::{
+    common::{
+        hash::{hash_file, hash_string, DVHashType},
+        serializer::{hex_deserializer, hex_serializer},
+    },
+    error::Result,
+};
+
+const CACHE_DIR_NAME: &str = "dver";
+
+//
+// files changed less than this before the walk started could change again
+// without moving their timestamps, they aren't cached
+//
+const CACHE_RACY_SECS: i64 = 2;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct CacheEntry {
+    dev: u64,
+    ino: u64,
+    size: u64,
+    mtime: i64,
+    mtime_nsec: i64,
+    ctime: i64,
+    ctime_nsec: i64,
+    hash_type: DVHashType,
+    #[serde(
+        serialize_with = "hex_serializer",
+        deserialize_with = "hex_deserializer"
+    )]
+    hash: Vec<u8>,
+}
+
+impl CacheEntry {
+    fn new(metadata: &Metadata, hash_type: DVHashType, hash: Vec<u8>) -> CacheEntry {
+        CacheEntry {
+            dev: metadata.dev(),
+            ino: metadata.ino(),
+            size: metadata.size(),
+            mtime: metadata.mtime(),
+            mtime_nsec: metadata.mtime_nsec(),
+            ctime: metadata.ctime(),
+            ctime_nsec: metadata.ctime_nsec(),
+            hash_type,
+            hash,
+        }
+    }
+
+    // everything but the hash has to match
+    fn matches(&self, other: &CacheEntry) -> bool {
+        self.dev == other.dev
+            && self.ino == other.ino
+            && self.size == other.size
+            && self.mtime == other.mtime
+            && self.mtime_nsec == other.mtime_nsec
+            && self.ctime == other.ctime
+            && self.ctime_nsec == other.ctime_nsec
+            && self.hash_type == other.hash_type
+    }
+}
+
+//
+// file hashes from the previous sign, keyed by inode and trusted as long as
+// size, mtime and ctime didn't move. Only what the walk sees is saved back so
+// the cache doesn't grow with removed files. Never used by verify
+//
+#[derive(Debug)]
+pub struct HashCache {
+    file: PathBuf,
+    paranoid: bool,
+    started: i64,
+    previous: HashMap<(u64, u64), CacheEntry>,
+    current: Mutex<Vec<CacheEntry>>,
+}
+
+impl HashCache {
+    pub fn load<P: AsRef<Path>>(file: P, paranoid: bool) -> Result<HashCache> {
+        let file = file.as_ref();
+
+        let entries: Vec<CacheEntry> = match fs::read(file) {
+            Ok(data) => match serde_json::from_slice(&data) {
+                Ok(v) => v,
+                Err(e) => {
+                    warn!("ignoring the invalid cache {}: {e}", file.display());
+                    vec![]
+                }
+            },
+            Err(_) => vec![],
+        };
+
+        info!("cache file={} entries={}", file.display(), entries.len());
+
+        let previous = entries.into_iter().map(|e| ((e.dev, e.ino), e)).collect();
+
+        let started = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+
+        Ok(HashCache {
+            file: file.into(),
+            paranoid,
+            started: started as i64,
+            previous,
+            current: Mutex::new(vec![]),
+        })
+    }
+
+    //
+    // $XDG_CACHE_HOME/dver/<sha256 of the directory path>.json
+    //
+    pub fn default_file<P: AsRef<Path>>(directory: P) -> Option<PathBuf> {
+        let cache_dir = match env::var_os("XDG_CACHE_HOME") {
+            Some(v) if !v.is_empty() => PathBuf::from(v),
+            _ => Path::new(&env::var_os("HOME")?).join(".cache"),
+        };
+
+        let directory = directory.as_ref().to_string_lossy();
+        let name = hex::encode(hash_string(&directory, DVHashType::Sha256));
+
+        Some(cache_dir.join(CACHE_DIR_NAME).join(format!("{name}.json")))
+    }
+
+    pub fn hash_file<P: AsRef<Path>>(
+        &self,
+        file: P,
+        metadata: &Metadata,
+        hash_type: DVHashType,
+    ) -> Result<Vec<u8>> {
+        let key = CacheEntry::new(metadata, hash_type, vec![]);
+
+        let cached = self
+            .previous
+            .get(&(key.dev, key.ino))
+            .filter(|e| e.matches(&key));
+
+        if let Some(entry) = cached
+            && !self.paranoid
+        {
+            self.add(entry.clone());
+            return Ok(entry.hash.clone());
+        }
+
+        let hash = hash_file(&file, hash_type)?;
+
+        if let Some(entry) = cached
+            && entry.hash != hash
+        {
+            warn!("stale cache entry for {}", file.as_ref().display());
+        }
+
+        if !self.is_racy(&key) {
+            self.add(CacheEntry {
+                hash: hash.clone(),
+                ..key
+            });
+        }
+
+        Ok(hash)
+    }
+
+    fn is_racy(&self, entry: &CacheEntry) -> bool {
+        let limit = self.started - CACHE_RACY_SECS;
+        entry.mtime >= limit || entry.ctime >= limit
+    }
+
+    fn add(&self, entry: CacheEntry) {
+        if let Ok(mut current) = self.current.lock() {
+            current.push(entry);
+        }
+    }
+
+    //
+    // written next to the destination and renamed, a reader never sees a
+    // partial cache
+    //
+    pub fn save(&self) -> Result<()> {
+        let mut entries = match self.current.lock() {
+            Ok(v) => v.clone(),
+            Err(_) => return Ok(()),
+        };
+
+        // nothing to keep and nothing to forget
+        if entries.is_empty() && self.previous.is_empty() {
+            return Ok(());
+        }
+
+        entries.sort_by_key(|e| (e.dev, e.ino));
+
+        let cache_dir = match self.file.parent() {
+            Some(v) => v,
+            None => Path::new("."),
+        };
+
+        fs::create_dir_all(cache_dir)?;
+
+        let mut tmp_file = NamedTempFile::new_in(cache_dir)?;
+        tmp_file.write_all(&serde_json::to_vec(&entries)?)?;
+        tmp_file.persist(&self.file).map_err(|e| e.error)?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use serde_json::Value;
+
+    use super::*;
+
+    #[test]
+    fn hash_cache() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let cache_file = tmp_dir.path().join("cache").join("cache.json");
+        let file = tmp_dir.path().join("file");
+
+        fs::write(&file, "hello").unwrap();
+        let metadata = fs::metadata(&file).unwrap();
+        let hash = hash_file(&file, DVHashType::Sha256).unwrap();
+
+        // just written, too recent to be trusted
+        let cache = HashCache::load(&cache_file, false).unwrap();
+        cache
+            .hash_file(&file, &metadata, DVHashType::Sha256)
+            .unwrap();
+        cache.save().unwrap();
+        assert!(!cache_file.exists());
+
+        // the ctime can't be set back, the walk starts later instead
+        let mut cache = HashCache::load(&cache_file, false).unwrap();
+        cache.started += CACHE_RACY_SECS + 1;
+        cache
+            .hash_file(&file, &metadata, DVHashType::Sha256)
+            .unwrap();
+        cache.save().unwrap();
+
+        // poison the cached hash, it's what the next sign gets
+        let mut entries: Value = serde_json::from_slice(&fs::read(&cache_file).unwrap()).unwrap();
+        entries[0]["hash"] = hex::encode([0; 32]).into();
+        fs::write(&cache_file, entries.to_string()).unwrap();
+
+        let cache = HashCache::load(&cache_file, false).unwrap();
+        let cached = cache
+            .hash_file(&file, &metadata, DVHashType::Sha256)
+            .unwrap();
+        assert_eq!(cached, vec![0; 32]);
+
+        // a different hash type is a miss
+        let cached = cache
+            .hash_file(&file, &metadata, DVHashType::Sha512)
+            .unwrap();
+        assert_eq!(cached, hash_file(&file, DVHashType::Sha512).unwrap());
+
+        let cache = HashCache::load(&cache_file, true).unwrap();
+        let cached = cache
+            .hash_file(&file, &metadata, DVHashType::Sha256)
+            .unwrap();
+        assert_eq!(cached, hash);
+
+        // anything unreadable is an empty cache
+        fs::write(&cache_file, "garbage").unwrap();
+        let cache = HashCache::load(&cache_file, false).unwrap();
+        let cached = cache
+            .hash_file(&file, &metadata, DVHashType::Sha256)
+            .unwrap();
+        assert_eq!(cached, hash);
+    }
+}
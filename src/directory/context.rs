@@ -0,0 +1,144 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    common::{hash::DVHashType, r#const::DEFAULT_SIGN_FILE_NAME},
+    error::Result,
+};
+
+use super::{
+    archive::ArchiveTree,
+    attributes::DVAttribute,
+    cache::HashCache,
+    exclude::ExcludeFilter,
+    special::DVSpecialPolicy,
+    walker::{WalkerHeader, HARDLINK_FORMAT_VER, SIGNATURE_FORMAT_VER, SYMLINK_FORMAT_VER},
+};
+
+//
+// everything the recursive walk needs to carry around
+//
+#[derive(Debug)]
+pub struct WalkerContext<'a> {
+    pub root: PathBuf,
+    pub directory: PathBuf,
+    pub canonical_directory: PathBuf,
+    pub follow_symlinks: bool,
+    // followed links have to stay inside the directory
+    pub confined: bool,
+    pub special_files: DVSpecialPolicy,
+    pub hardlinks: bool,
+    pub hash_type: DVHashType,
+    pub version: u8,
+    pub exclude: ExcludeFilter,
+    pub attributes: Vec<DVAttribute>,
+    pub xattrs: Vec<String>,
+    // the signature the manifest was made with, older formats skipped any
+    // file named like one
+    pub signature: Option<PathBuf>,
+    pub legacy_signatures: bool,
+    pub subtrees: Vec<PathBuf>,
+    pub scope: Option<PathBuf>,
+    pub cache: Option<&'a HashCache>,
+    // the entries of the archive being walked, the filesystem when None
+    pub archive: Option<&'a ArchiveTree>,
+}
+
+impl<'a> WalkerContext<'a> {
+    pub fn new<P: AsRef<Path>, T: AsRef<Path>>(
+        root: P,
+        directory: T,
+        hash_type: DVHashType,
+        header: &WalkerHeader,
+    ) -> Result<WalkerContext<'a>> {
+        let canonical_directory = fs::canonicalize(&directory)?;
+        WalkerContext::with_canonical(root, directory, canonical_directory, hash_type, header)
+    }
+
+    //
+    // the directory of an archive only exists in the manifest, it's its own
+    // canonical path
+    //
+    pub fn new_archive<P: AsRef<Path>, T: AsRef<Path>>(
+        root: P,
+        directory: T,
+        hash_type: DVHashType,
+        header: &WalkerHeader,
+    ) -> Result<WalkerContext<'a>> {
+        let canonical_directory = directory.as_ref().into();
+        WalkerContext::with_canonical(root, directory, canonical_directory, hash_type, header)
+    }
+
+    fn with_canonical<P: AsRef<Path>, T: AsRef<Path>>(
+        root: P,
+        directory: T,
+        canonical_directory: PathBuf,
+        hash_type: DVHashType,
+        header: &WalkerHeader,
+    ) -> Result<WalkerContext<'a>> {
+        let exclude = ExcludeFilter::new(&directory, &header.exclude)?;
+
+        // older releases followed every link, wherever it pointed
+        let legacy = header.version < SYMLINK_FORMAT_VER;
+
+        Ok(WalkerContext {
+            root: root.as_ref().into(),
+            directory: directory.as_ref().into(),
+            canonical_directory,
+            follow_symlinks: header.follow_symlinks || legacy,
+            confined: !legacy,
+            special_files: header.special_files,
+            hardlinks: header.version >= HARDLINK_FORMAT_VER,
+            hash_type,
+            version: header.version,
+            exclude,
+            attributes: header.attributes.clone(),
+            xattrs: header.xattrs.clone(),
+            signature: header
+                .signature
+                .as_ref()
+                .map(|s| directory.as_ref().join(s)),
+            legacy_signatures: header.version < SIGNATURE_FORMAT_VER,
+            subtrees: header
+                .subtrees
+                .iter()
+                .map(|s| directory.as_ref().join(s))
+                .collect(),
+            scope: None,
+            cache: None,
+            archive: None,
+        })
+    }
+
+    //
+    // any other dver.sig is content like the rest, it could have been planted
+    // there to look like it doesn't matter. verify reports them
+    //
+    pub fn is_signature(&self, path: &Path) -> bool {
+        if self.signature.as_deref() == Some(path) {
+            return true;
+        }
+
+        self.legacy_signatures && path.file_name() == Some(DEFAULT_SIGN_FILE_NAME.as_ref())
+    }
+
+    //
+    // with a scope only the path itself and what's below it are read, the
+    // directories leading to it are only walked through
+    //
+    pub fn in_scope(&self, path: &Path) -> bool {
+        match &self.scope {
+            Some(scope) => path.starts_with(scope),
+            None => true,
+        }
+    }
+
+    pub fn leads_to_scope(&self, path: &Path) -> bool {
+        match &self.scope {
+            Some(scope) => scope != path && scope.starts_with(path),
+            None => false,
+        }
+    }
+}
@@ -0,0 +1,443 @@
+use core::fmt;
+use std::{
+    collections::BTreeMap,
+    mem::discriminant,
+    path::{Path, PathBuf},
+};
+
+use serde_derive::Serialize;
+
+use super::{
+    attributes::WalkerAttributes,
+    dir::WalkerDirectory,
+    xattrs::{diff_xattrs, WalkerXattr},
+};
+
+#[derive(Debug, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WalkerChangeKind {
+    Added,
+    Removed,
+    Modified,
+    Replaced,
+    Hardlinks,
+    Xattrs,
+    Permissions,
+}
+
+impl fmt::Display for WalkerChangeKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            WalkerChangeKind::Added => write!(f, "Added"),
+            WalkerChangeKind::Removed => write!(f, "Removed"),
+            WalkerChangeKind::Modified => write!(f, "Modified"),
+            WalkerChangeKind::Replaced => write!(f, "Replaced"),
+            WalkerChangeKind::Hardlinks => write!(f, "Hardlinks"),
+            WalkerChangeKind::Xattrs => write!(f, "Xattrs"),
+            WalkerChangeKind::Permissions => write!(f, "Permissions"),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WalkerEntryType {
+    File,
+    Directory,
+    Symlink,
+    Special,
+    Subtree,
+}
+
+impl fmt::Display for WalkerEntryType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            WalkerEntryType::File => write!(f, "file"),
+            WalkerEntryType::Directory => write!(f, "directory"),
+            WalkerEntryType::Symlink => write!(f, "symlink"),
+            WalkerEntryType::Special => write!(f, "special"),
+            WalkerEntryType::Subtree => write!(f, "subtree"),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct WalkerChange {
+    pub kind: WalkerChangeKind,
+    pub path: PathBuf,
+    pub directory: bool,
+    #[serde(rename = "type")]
+    pub entry_type: WalkerEntryType,
+    // the extended attribute that changed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub xattr: Option<String>,
+}
+
+impl WalkerChange {
+    fn new(kind: WalkerChangeKind, path: &Path, entry: &Entry) -> WalkerChange {
+        let entry_type = match entry {
+            Entry::File(..) => WalkerEntryType::File,
+            Entry::Directory => WalkerEntryType::Directory,
+            Entry::Symlink(_) => WalkerEntryType::Symlink,
+            Entry::Special(_) => WalkerEntryType::Special,
+            Entry::Subtree(_) => WalkerEntryType::Subtree,
+        };
+
+        WalkerChange {
+            kind,
+            path: path.into(),
+            directory: entry_type == WalkerEntryType::Directory,
+            entry_type,
+            xattr: None,
+        }
+    }
+}
+
+impl fmt::Display for WalkerChange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.xattr {
+            Some(xattr) => write!(f, "{} ({}, {xattr})", self.path.display(), self.entry_type),
+            None => write!(f, "{} ({})", self.path.display(), self.entry_type),
+        }
+    }
+}
+
+// content hash and mode, the hash of what stands for the content otherwise
+#[derive(PartialEq)]
+enum Entry<'a> {
+    File(&'a [u8], Option<u32>),
+    Directory,
+    Symlink(&'a [u8]),
+    Special(&'a [u8]),
+    Subtree(&'a [u8]),
+}
+
+struct EntryInfo<'a> {
+    entry: Entry<'a>,
+    attributes: &'a WalkerAttributes,
+    hardlink: Option<&'a Path>,
+    xattrs: &'a [WalkerXattr],
+}
+
+impl<'a> EntryInfo<'a> {
+    fn new(entry: Entry<'a>, attributes: &'a WalkerAttributes) -> EntryInfo<'a> {
+        EntryInfo {
+            entry,
+            attributes,
+            hardlink: None,
+            xattrs: &[],
+        }
+    }
+}
+
+fn flatten<'a>(dir: &'a WalkerDirectory, entries: &mut BTreeMap<&'a Path, EntryInfo<'a>>) {
+    let mut info = EntryInfo::new(Entry::Directory, &dir.attributes);
+    info.xattrs = &dir.xattrs;
+    entries.insert(&dir.directory, info);
+
+    for file in &dir.files {
+        // with the full mode recorded, the executable bit is a permission change
+        let mode = match file.attributes.permissions {
+            Some(_) => None,
+            None => file.mode,
+        };
+
+        let mut info = EntryInfo::new(Entry::File(&file.hash, mode), &file.attributes);
+        info.hardlink = file.hardlink.as_deref();
+        info.xattrs = &file.xattrs;
+        entries.insert(&file.path, info);
+    }
+
+    for link in &dir.symlinks {
+        let info = EntryInfo::new(Entry::Symlink(&link.hash), &link.attributes);
+        entries.insert(&link.path, info);
+    }
+
+    for special in &dir.specials {
+        let info = EntryInfo::new(Entry::Special(&special.hash), &special.attributes);
+        entries.insert(&special.path, info);
+    }
+
+    for subtree in &dir.subtrees {
+        let info = EntryInfo::new(Entry::Subtree(&subtree.hash), &WalkerAttributes::NONE);
+        entries.insert(&subtree.path, info);
+    }
+
+    for sub_dir in &dir.directories {
+        flatten(sub_dir, entries);
+    }
+}
+
+fn entries(dir: Option<&WalkerDirectory>) -> BTreeMap<&Path, EntryInfo<'_>> {
+    let mut entries = BTreeMap::new();
+
+    if let Some(dir) = dir {
+        flatten(dir, &mut entries);
+    }
+
+    entries
+}
+
+////////////////////////////////////////////////////////////////////////////////
+/// PUBLIC
+////////////////////////////////////////////////////////////////////////////////
+pub fn diff_directories(
+    signed: Option<&WalkerDirectory>,
+    current: Option<&WalkerDirectory>,
+) -> Vec<WalkerChange> {
+    let signed = entries(signed);
+    let current = entries(current);
+
+    let mut changes = Vec::new();
+
+    //
+    // content changes take precedence, hardlink drift is reported on its own.
+    // xattr and permission drift are both reported so neither hides the
+    // other, every xattr that changed is a change of its own
+    //
+    for (path, s) in &signed {
+        let c = match current.get(path) {
+            Some(v) => v,
+            None => {
+                changes.push(WalkerChange::new(WalkerChangeKind::Removed, path, &s.entry));
+                continue;
+            }
+        };
+
+        let kind = match c {
+            // a file swapped for a link or a directory
+            c if discriminant(&c.entry) != discriminant(&s.entry) => {
+                Some(WalkerChangeKind::Replaced)
+            }
+            c if c.entry != s.entry => Some(WalkerChangeKind::Modified),
+            c if c.hardlink != s.hardlink => Some(WalkerChangeKind::Hardlinks),
+            _ => None,
+        };
+
+        if let Some(kind) = kind {
+            changes.push(WalkerChange::new(kind, path, &c.entry));
+            continue;
+        }
+
+        for xattr in diff_xattrs(s.xattrs, c.xattrs) {
+            let mut change = WalkerChange::new(WalkerChangeKind::Xattrs, path, &c.entry);
+            change.xattr = Some(xattr);
+            changes.push(change);
+        }
+
+        if c.attributes != s.attributes {
+            changes.push(WalkerChange::new(
+                WalkerChangeKind::Permissions,
+                path,
+                &c.entry,
+            ));
+        }
+    }
+
+    for (path, c) in &current {
+        if !signed.contains_key(path) {
+            changes.push(WalkerChange::new(WalkerChangeKind::Added, path, &c.entry));
+        }
+    }
+
+    changes.sort_by(|a, b| a.path.cmp(&b.path));
+
+    changes
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::{
+        fs,
+        os::unix::{
+            fs::{symlink, PermissionsExt},
+            net::UnixListener,
+        },
+    };
+
+    use crate::{
+        common::{hash::DVHashType, testing::write_test_tree},
+        directory::{attributes::DVAttribute, special::DVSpecialPolicy, walker::WalkerHeader},
+    };
+
+    use super::*;
+
+    #[test]
+    fn diff_tree() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let product = tmp_dir.path().join("product");
+        write_test_tree(&product);
+
+        let signed =
+            WalkerDirectory::new(&product, DVHashType::Sha256, &WalkerHeader::default()).unwrap();
+
+        let changes = diff_directories(Some(&signed), Some(&signed));
+        assert!(changes.is_empty());
+
+        fs::write(product.join("server.py"), "print('bye')\n").unwrap();
+        fs::remove_file(product.join("www").join("index.html")).unwrap();
+        fs::create_dir(product.join("www").join("css")).unwrap();
+        fs::write(product.join("www").join("css").join("a.css"), "").unwrap();
+
+        let current =
+            WalkerDirectory::new(&product, DVHashType::Sha256, &WalkerHeader::default()).unwrap();
+
+        let changes = diff_directories(Some(&signed), Some(&current));
+
+        let found: Vec<(&WalkerChangeKind, String)> = changes
+            .iter()
+            .map(|c| (&c.kind, c.path.display().to_string()))
+            .collect();
+
+        assert_eq!(
+            found,
+            vec![
+                (&WalkerChangeKind::Modified, "product/server.py".into()),
+                (&WalkerChangeKind::Added, "product/www/css".into()),
+                (&WalkerChangeKind::Added, "product/www/css/a.css".into()),
+                (&WalkerChangeKind::Removed, "product/www/index.html".into()),
+            ]
+        );
+
+        assert!(changes[1].directory);
+        assert!(!changes[2].directory);
+    }
+
+    #[test]
+    fn diff_hardlinks() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let product = tmp_dir.path().join("product");
+        write_test_tree(&product);
+
+        let server = product.join("server.py");
+        let main = product.join("main.py");
+        fs::hard_link(&server, &main).unwrap();
+
+        let header = WalkerHeader::default();
+        let signed = WalkerDirectory::new(&product, DVHashType::Sha256, &header).unwrap();
+
+        // same content, no longer the same file
+        fs::remove_file(&server).unwrap();
+        fs::copy(&main, &server).unwrap();
+
+        let current = WalkerDirectory::new(&product, DVHashType::Sha256, &header).unwrap();
+
+        let changes = diff_directories(Some(&signed), Some(&current));
+
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].kind, WalkerChangeKind::Hardlinks);
+        assert_eq!(changes[0].path, Path::new("product/server.py"));
+    }
+
+    #[test]
+    fn diff_permissions() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let product = tmp_dir.path().join("product");
+        write_test_tree(&product);
+
+        let socket = product.join("app.sock");
+        let _listener = UnixListener::bind(&socket).unwrap();
+
+        let header = WalkerHeader {
+            attributes: vec![DVAttribute::Mode],
+            special_files: DVSpecialPolicy::Record,
+            ..Default::default()
+        };
+        let signed = WalkerDirectory::new(&product, DVHashType::Sha256, &header).unwrap();
+
+        // the executable bit alone is no content change
+        let server = product.join("server.py");
+        fs::set_permissions(&server, fs::Permissions::from_mode(0o755)).unwrap();
+        fs::set_permissions(&socket, fs::Permissions::from_mode(0o600)).unwrap();
+
+        let current = WalkerDirectory::new(&product, DVHashType::Sha256, &header).unwrap();
+        assert_ne!(signed.hash(), current.hash());
+
+        let changes = diff_directories(Some(&signed), Some(&current));
+
+        let found: Vec<String> = changes.iter().map(|c| c.to_string()).collect();
+        assert_eq!(
+            found,
+            ["product/app.sock (special)", "product/server.py (file)"]
+        );
+        assert!(changes
+            .iter()
+            .all(|c| c.kind == WalkerChangeKind::Permissions));
+    }
+
+    #[test]
+    fn diff_xattrs_changes() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let product = tmp_dir.path().join("product");
+        write_test_tree(&product);
+
+        let server = product.join("server.py");
+        xattr::set(&server, "user.dver.label", b"bin_t").unwrap();
+
+        let header = WalkerHeader {
+            attributes: vec![DVAttribute::Mode],
+            xattrs: vec!["user.dver".into()],
+            ..Default::default()
+        };
+        let signed = WalkerDirectory::new(&product, DVHashType::Sha256, &header).unwrap();
+
+        xattr::set(&server, "user.dver.label", b"unconfined_t").unwrap();
+        xattr::set(&server, "user.dver.cap", b"").unwrap();
+        fs::set_permissions(&server, fs::Permissions::from_mode(0o600)).unwrap();
+        xattr::set(product.join("www"), "user.dver.label", b"httpd_t").unwrap();
+
+        let current = WalkerDirectory::new(&product, DVHashType::Sha256, &header).unwrap();
+        assert_ne!(signed.hash(), current.hash());
+
+        let changes = diff_directories(Some(&signed), Some(&current));
+
+        let found: Vec<(&WalkerChangeKind, String)> =
+            changes.iter().map(|c| (&c.kind, c.to_string())).collect();
+        assert_eq!(
+            found,
+            [
+                (
+                    &WalkerChangeKind::Xattrs,
+                    "product/server.py (file, user.dver.cap)".into()
+                ),
+                (
+                    &WalkerChangeKind::Xattrs,
+                    "product/server.py (file, user.dver.label)".into()
+                ),
+                (
+                    &WalkerChangeKind::Permissions,
+                    "product/server.py (file)".into()
+                ),
+                (
+                    &WalkerChangeKind::Xattrs,
+                    "product/www (directory, user.dver.label)".into()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn diff_replaced() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let product = tmp_dir.path().join("product");
+        write_test_tree(&product);
+
+        let header = WalkerHeader::default();
+        let signed = WalkerDirectory::new(&product, DVHashType::Sha256, &header).unwrap();
+
+        // same content, but through a link
+        let server = product.join("server.py");
+        fs::rename(&server, product.join("www").join("server.py")).unwrap();
+        symlink("www/server.py", &server).unwrap();
+
+        let current = WalkerDirectory::new(&product, DVHashType::Sha256, &header).unwrap();
+
+        let changes = diff_directories(Some(&signed), Some(&current));
+
+        assert_eq!(changes[0].kind, WalkerChangeKind::Replaced);
+        assert_eq!(changes[0].path, Path::new("product/server.py"));
+        assert_eq!(changes[0].entry_type, WalkerEntryType::Symlink);
+        assert_eq!(changes[0].to_string(), "product/server.py (symlink)");
+    }
+}
@@ -1,30 +1,266 @@
 use std::{
+    collections::BTreeMap,
     env,
     fmt::{self},
-    fs,
+    fs::{self, Metadata},
     path::{Path, PathBuf},
     vec,
 };
 
 use crate::common::{
-    hash::DVHashType, r#const::DEFAULT_SIGN_FILE_NAME, serializer::hex_serializer,
+    hash::{DVHashType, DVHasher},
+    r#const::DEFAULT_SIGN_FILE_NAME,
+    serializer::{hex_deserializer, hex_serializer},
 };
 
-use log::info;
-use serde_derive::Serialize;
-use sha2::{Digest, Sha256, Sha512};
+use log::{info, warn};
+use rayon::prelude::*;
+use serde_derive::{Deserialize, Serialize};
 
 use crate::error::Error;
 
-use super::file::WalkerFile;
+use super::{
+    archive::{ArchiveEntry, ArchiveEntryKind, ArchiveTree, DVArchiveType},
+    attributes::{DVAttribute, WalkerAttributes},
+    cache::HashCache,
+    context::WalkerContext,
+    file::WalkerFile,
+    special::{DVSpecialPolicy, WalkerSpecial},
+    subtree::WalkerSubtree,
+    symlink::{link_escapes, target_escapes, WalkerSymlink},
+    walker::{WalkerHeader, MERKLE_FORMAT_VER},
+    xattrs::{encode_xattrs, read_xattrs, select_xattrs, WalkerXattr},
+};
+
+const MERKLE_FILE: u8 = b'f';
+const MERKLE_DIR: u8 = b'd';
+const MERKLE_DIR_MODE: u32 = 0o755;
+const MERKLE_LINK: u8 = b'l';
+const MERKLE_LINK_MODE: u32 = 0o777;
+const MERKLE_SPECIAL: u8 = b's';
+const MERKLE_SPECIAL_MODE: u32 = 0;
+const MERKLE_HARDLINK: u8 = b'h';
+const MERKLE_SUBTREE: u8 = b'n';
+
+fn entry_name(path: &Path) -> Result<&[u8], Error> {
+    match path.file_name() {
+        Some(v) => Ok(v.as_encoded_bytes()),
+        None => Err(Error::InvalidPath(format!("{:?} has no name", path))),
+    }
+}
+
+//
+// an entry of the directory being walked, read from the filesystem or listed
+// in an archive. The rest of the walk is the same for both
+//
+enum WalkerSource<'a> {
+    Fs(Metadata),
+    Archive(&'a ArchiveEntry),
+}
+
+impl<'a> WalkerSource<'a> {
+    //
+    // the entry itself, links aren't followed yet, an excluded one never is
+    //
+    fn new(ctx: &WalkerContext<'a>, entry: &Path) -> Result<WalkerSource<'a>, Error> {
+        match ctx.archive {
+            Some(tree) => {
+                let entry = WalkerSource::archive_entry(ctx, tree, entry)?;
+                Ok(WalkerSource::Archive(entry))
+            }
+            None => Ok(WalkerSource::Fs(fs::symlink_metadata(entry)?)),
+        }
+    }
+
+    fn follow(
+        self,
+        ctx: &WalkerContext,
+        entry: &Path,
+        ancestors: &[PathBuf],
+    ) -> Result<WalkerSource<'a>, Error> {
+        match self {
+            WalkerSource::Fs(m) if m.is_symlink() && ctx.follow_symlinks => {
+                let metadata = WalkerSource::follow_symlink(ctx, entry, ancestors)?;
+                Ok(WalkerSource::Fs(metadata))
+            }
+            source => Ok(source),
+        }
+    }
+
+    fn archive_entry(
+        ctx: &WalkerContext,
+        tree: &'a ArchiveTree,
+        path: &Path,
+    ) -> Result<&'a ArchiveEntry, Error> {
+        let rel_path = path.strip_prefix(&ctx.directory)?;
+
+        match tree.get(rel_path) {
+            Some(v) => Ok(v),
+            None => {
+                let err = format!("{:?} isn't in the archive", rel_path);
+                Err(Error::NotFound(err))
+            }
+        }
+    }
+
+    //
+    // a followed link must resolve inside the directory and can't point back
+    // to one of the directories being walked
+    //
+    fn follow_symlink(
+        ctx: &WalkerContext,
+        link: &Path,
+        ancestors: &[PathBuf],
+    ) -> Result<Metadata, Error> {
+        let target = match fs::canonicalize(link) {
+            Ok(v) => v,
+            Err(_) => {
+                let err = format!("{:?} is a dangling link", link);
+                return Err(Error::InvalidPath(err));
+            }
+        };
+
+        if ctx.confined && !target.starts_with(&ctx.canonical_directory) {
+            let err = format!("{:?} points outside of the directory", link);
+            return Err(Error::InvalidPath(err));
+        }
+
+        if ancestors.contains(&target) {
+            let err = format!("{:?} loops back to {:?}", link, target);
+            return Err(Error::InvalidPath(err));
+        }
+
+        Ok(fs::metadata(target)?)
+    }
+
+    fn is_dir(&self) -> bool {
+        match self {
+            WalkerSource::Fs(m) => m.is_dir(),
+            WalkerSource::Archive(e) => e.kind == ArchiveEntryKind::Directory,
+        }
+    }
+
+    fn is_file(&self) -> bool {
+        match self {
+            WalkerSource::Fs(m) => m.is_file(),
+            WalkerSource::Archive(e) => matches!(e.kind, ArchiveEntryKind::File(_)),
+        }
+    }
+
+    fn is_symlink(&self) -> bool {
+        match self {
+            WalkerSource::Fs(m) => m.is_symlink(),
+            WalkerSource::Archive(e) => matches!(e.kind, ArchiveEntryKind::Symlink(_)),
+        }
+    }
+
+    fn link_escapes(&self, ctx: &WalkerContext, link: &Path) -> Result<bool, Error> {
+        match self {
+            WalkerSource::Fs(_) => link_escapes(&ctx.directory, link),
+            WalkerSource::Archive(e) => match &e.kind {
+                ArchiveEntryKind::Symlink(target) => {
+                    Ok(target_escapes(&ctx.directory, link, target))
+                }
+                _ => Ok(false),
+            },
+        }
+    }
+
+    fn file(&self, ctx: &WalkerContext, file: &Path) -> Result<WalkerFile, Error> {
+        let mut f = match self {
+            WalkerSource::Fs(_) => WalkerFile::new(
+                &ctx.root,
+                file,
+                ctx.hash_type,
+                ctx.cache,
+                &ctx.attributes,
+                &ctx.xattrs,
+            )?,
+            WalkerSource::Archive(e) => {
+                let hash = match &e.kind {
+                    ArchiveEntryKind::File(hash) => hash.clone(),
+                    _ => return Err(Error::InvalidPath(format!("{:?} is not a file", file))),
+                };
+
+                let mut f = WalkerFile::with_hash(
+                    file.strip_prefix(&ctx.root)?,
+                    hash,
+                    e.mode(),
+                    e.attributes(file, &ctx.attributes)?,
+                    select_xattrs(&e.xattrs, &ctx.xattrs),
+                );
+
+                f.inode = e.inode.map(|v| (0, v));
+                f
+            }
+        };
 
-#[derive(Debug, Serialize)]
+        if ctx.version < MERKLE_FORMAT_VER {
+            f.mode = None;
+        }
+
+        Ok(f)
+    }
+
+    fn symlink(&self, ctx: &WalkerContext, link: &Path) -> Result<WalkerSymlink, Error> {
+        match self {
+            WalkerSource::Fs(_) => {
+                WalkerSymlink::new(&ctx.root, link, ctx.hash_type, &ctx.attributes)
+            }
+            WalkerSource::Archive(e) => match &e.kind {
+                ArchiveEntryKind::Symlink(target) => Ok(WalkerSymlink::with_target(
+                    link.strip_prefix(&ctx.root)?,
+                    target.clone(),
+                    ctx.hash_type,
+                    e.attributes(link, &ctx.attributes)?,
+                )),
+                _ => Err(Error::InvalidPath(format!("{:?} is not a link", link))),
+            },
+        }
+    }
+
+    fn special(&self, ctx: &WalkerContext, special: &Path) -> Result<WalkerSpecial, Error> {
+        match self {
+            WalkerSource::Fs(m) => {
+                WalkerSpecial::new(&ctx.root, special, m, ctx.hash_type, &ctx.attributes)
+            }
+            WalkerSource::Archive(e) => match &e.kind {
+                ArchiveEntryKind::Special(kind, device) => Ok(WalkerSpecial::with_kind(
+                    special.strip_prefix(&ctx.root)?,
+                    *kind,
+                    *device,
+                    ctx.hash_type,
+                    e.attributes(special, &ctx.attributes)?,
+                )),
+                _ => Err(Error::InvalidPath(format!(
+                    "{:?} is not a special file",
+                    special
+                ))),
+            },
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
 pub struct WalkerDirectory {
-    directory: PathBuf,
-    #[serde(serialize_with = "hex_serializer")]
+    pub directory: PathBuf,
+    #[serde(
+        serialize_with = "hex_serializer",
+        deserialize_with = "hex_deserializer"
+    )]
     hash: Vec<u8>,
-    files: Vec<WalkerFile>,
-    directories: Vec<WalkerDirectory>,
+    pub files: Vec<WalkerFile>,
+    pub directories: Vec<WalkerDirectory>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub symlinks: Vec<WalkerSymlink>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub specials: Vec<WalkerSpecial>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub subtrees: Vec<WalkerSubtree>,
+    #[serde(flatten)]
+    pub attributes: WalkerAttributes,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub xattrs: Vec<WalkerXattr>,
 }
 
 impl fmt::Display for WalkerDirectory {
@@ -46,7 +282,25 @@ impl fmt::Display for WalkerDirectory {
 }
 
 impl WalkerDirectory {
-    pub fn new<P: AsRef<Path>>(dir: P, hash_type: DVHashType) -> Result<WalkerDirectory, Error> {
+    pub fn new<P: AsRef<Path>>(
+        dir: P,
+        hash_type: DVHashType,
+        header: &WalkerHeader,
+    ) -> Result<WalkerDirectory, Error> {
+        WalkerDirectory::new_with_scope(dir, hash_type, header, None, None)
+    }
+
+    //
+    // with a scope, relative to dir, only the entries leading to it and the
+    // ones below it are walked
+    //
+    pub fn new_with_scope<P: AsRef<Path>>(
+        dir: P,
+        hash_type: DVHashType,
+        header: &WalkerHeader,
+        scope: Option<&Path>,
+        cache: Option<&HashCache>,
+    ) -> Result<WalkerDirectory, Error> {
         let dir: PathBuf = match dir.as_ref().is_absolute() {
             true => dir.as_ref().into(),
             false => {
@@ -56,113 +310,536 @@ impl WalkerDirectory {
         };
 
         match dir.parent() {
-            Some(root) => WalkerDirectory::new_with_root(root, &dir, hash_type),
+            Some(root) => {
+                let mut ctx = WalkerContext::new(root, &dir, hash_type, header)?;
+                ctx.scope = scope.map(|s| dir.join(s));
+                ctx.cache = cache;
+
+                WalkerDirectory::new_root(&ctx)
+            }
             None => Err(Error::InvalidRootDirectory),
         }
     }
 
-    fn ignore_file(&self, file_path: &PathBuf) -> bool {
-        if !file_path.is_file() {
-            return false;
-        }
+    //
+    // an archive is walked as the directory it unpacks to, named after the
+    // archive without its extension
+    //
+    pub fn from_archive<P: AsRef<Path>>(
+        archive: P,
+        hash_type: DVHashType,
+        header: &WalkerHeader,
+        scope: Option<&Path>,
+    ) -> Result<WalkerDirectory, Error> {
+        let archive = archive.as_ref();
 
-        match file_path.file_name() {
-            Some(basename) => {
-                if basename == DEFAULT_SIGN_FILE_NAME {
-                    info!("ignoring file={:?}", file_path);
-                    true
-                } else {
-                    false
-                }
+        let (archive_type, name) = match DVArchiveType::from_path(archive) {
+            Some(v) => v,
+            None => {
+                let err = format!("{:?} isn't a supported archive", archive);
+                return Err(Error::InvalidArgument(err));
             }
-            None => false,
+        };
+
+        if header.follow_symlinks {
+            let err = "links can't be followed in an archive".to_string();
+            return Err(Error::InvalidArgument(err));
         }
+
+        if !header.subtrees.is_empty() {
+            let err = "subtrees can't be verified in an archive".to_string();
+            return Err(Error::InvalidArgument(err));
+        }
+
+        let archive = match archive.is_absolute() {
+            true => archive.into(),
+            false => env::current_dir()?.join(archive),
+        };
+
+        let root = match archive.parent() {
+            Some(v) => v,
+            None => return Err(Error::InvalidRootDirectory),
+        };
+
+        let tree = ArchiveTree::read(&archive, archive_type, hash_type, &name)?;
+
+        let dir = root.join(name);
+
+        let mut ctx = WalkerContext::new_archive(root, &dir, hash_type, header)?;
+        ctx.scope = scope.map(|s| dir.join(s));
+        ctx.archive = Some(&tree);
+
+        if !tree.xattrs && !ctx.xattrs.is_empty() {
+            warn!("{archive_type} archives don't record extended attributes");
+        }
+
+        WalkerDirectory::new_root(&ctx)
     }
 
-    fn new_with_root<P: AsRef<Path>, T: AsRef<Path>>(
-        root: P,
+    //
+    // a directory and an archive are walked the same way, only their entries
+    // are read differently
+    //
+    fn new_root(ctx: &WalkerContext) -> Result<WalkerDirectory, Error> {
+        let mut d = WalkerDirectory::new_with_context(ctx, &ctx.directory, &[])?;
+
+        // a scoped walk can't see every name of an inode
+        if ctx.hardlinks && ctx.scope.is_none() {
+            d.link_hardlinks(ctx.hash_type, ctx.version)?;
+        }
+
+        Ok(d)
+    }
+
+    pub fn contains(&self, path: &Path) -> bool {
+        if self.directory == path
+            || self.files.iter().any(|f| f.path == path)
+            || self.symlinks.iter().any(|l| l.path == path)
+            || self.specials.iter().any(|s| s.path == path)
+            || self.subtrees.iter().any(|s| s.path == path)
+        {
+            return true;
+        }
+
+        self.directories
+            .iter()
+            .any(|d| path.starts_with(&d.directory) && d.contains(path))
+    }
+
+    //
+    // files named like a signature, wherever they are
+    //
+    pub fn signature_files(&self) -> Vec<&Path> {
+        let mut files: Vec<&Path> = self
+            .files
+            .iter()
+            .filter(|f| f.path.file_name() == Some(DEFAULT_SIGN_FILE_NAME.as_ref()))
+            .map(|f| f.path.as_path())
+            .collect();
+
+        for dir in &self.directories {
+            files.extend(dir.signature_files());
+        }
+
+        files
+    }
+
+    fn new_with_context<T: AsRef<Path>>(
+        ctx: &WalkerContext,
         dir: T,
-        hash_type: DVHashType,
+        ancestors: &[PathBuf],
     ) -> Result<WalkerDirectory, Error> {
-        let rel_name = dir.as_ref().strip_prefix(&root)?;
+        let dir = dir.as_ref();
+        let rel_name = dir.strip_prefix(&ctx.root)?;
+
+        let (attributes, xattrs) = match ctx.archive {
+            Some(tree) => {
+                let entry = WalkerSource::archive_entry(ctx, tree, dir)?;
+                let xattrs = select_xattrs(&entry.xattrs, &ctx.xattrs);
+                (entry.attributes(dir, &ctx.attributes)?, xattrs)
+            }
+            None => {
+                let metadata = fs::metadata(dir)?;
+                let xattrs = read_xattrs(dir, &ctx.xattrs)?;
+                (WalkerAttributes::new(&metadata, &ctx.attributes), xattrs)
+            }
+        };
 
         let mut d = WalkerDirectory {
             directory: rel_name.into(),
             files: Vec::new(),
             directories: Vec::new(),
+            symlinks: Vec::new(),
+            specials: Vec::new(),
+            subtrees: Vec::new(),
             hash: vec![],
+            attributes,
+            xattrs,
         };
 
-        d.parse(root, dir, hash_type)?;
+        // only following links can bring the walk back to where it was
+        let mut ancestors = ancestors.to_vec();
 
-        d.hash = match hash_type {
-            DVHashType::Sha256 => d.hash::<Sha256>(),
-            DVHashType::Sha512 => d.hash::<Sha512>(),
-        }?;
+        if ctx.follow_symlinks {
+            ancestors.push(fs::canonicalize(dir)?);
+        }
+
+        d.parse(ctx, dir, &ancestors)?;
+
+        d.hash = d.compute_hash(ctx.hash_type, ctx.version)?;
 
         Ok(d)
     }
 
-    fn parse<P: AsRef<Path>, T: AsRef<Path>>(
+    pub fn hash(&self) -> &[u8] {
+        &self.hash
+    }
+
+    //
+    // recomputes every directory hash from the manifest itself, a manifest
+    // that passes can be trusted from its root hash alone
+    //
+    pub fn check_hash(&self, hash_type: DVHashType, version: u8) -> Result<(), Error> {
+        for dir in &self.directories {
+            dir.check_hash(hash_type, version)?;
+        }
+
+        match self.compute_hash(hash_type, version)? == self.hash {
+            true => Ok(()),
+            false => Err(Error::VerificationFailure),
+        }
+    }
+
+    pub fn retain_attributes(
         &mut self,
-        root: P,
-        dir: T,
+        attributes: &[DVAttribute],
         hash_type: DVHashType,
+        version: u8,
     ) -> Result<(), Error> {
-        if dir.as_ref().is_dir() {
-            for entry in fs::read_dir(&dir)? {
-                let entry = entry?.path();
-                if entry.is_dir() {
-                    let d = WalkerDirectory::new_with_root(root.as_ref(), entry, hash_type)?;
-                    self.directories.push(d);
-                } else if entry.is_file() {
-                    // we'll do it later
-                } else {
-                    let err = format!("{:?} is not a file or directory", entry);
-                    return Err(Error::InvalidPath(err));
+        self.attributes.retain(attributes);
+
+        for file in &mut self.files {
+            file.attributes.retain(attributes);
+        }
+
+        for link in &mut self.symlinks {
+            link.attributes.retain(attributes);
+        }
+
+        for special in &mut self.specials {
+            special.attributes.retain(attributes);
+        }
+
+        for dir in &mut self.directories {
+            dir.retain_attributes(attributes, hash_type, version)?;
+        }
+
+        self.hash = self.compute_hash(hash_type, version)?;
+
+        Ok(())
+    }
+
+    fn inodes<'a>(&'a self, groups: &mut BTreeMap<(u64, u64), Vec<&'a Path>>) {
+        for file in &self.files {
+            if let Some(inode) = file.inode {
+                groups.entry(inode).or_default().push(&file.path);
+            }
+        }
+
+        for dir in &self.directories {
+            dir.inodes(groups);
+        }
+    }
+
+    fn set_hardlinks(
+        &mut self,
+        leaders: &BTreeMap<PathBuf, PathBuf>,
+        hash_type: DVHashType,
+        version: u8,
+    ) -> Result<(), Error> {
+        for file in &mut self.files {
+            file.hardlink = leaders.get(&file.path).cloned();
+        }
+
+        for dir in &mut self.directories {
+            dir.set_hardlinks(leaders, hash_type, version)?;
+        }
+
+        self.hash = self.compute_hash(hash_type, version)?;
+
+        Ok(())
+    }
+
+    //
+    // files sharing an inode point to the first of their names, byte-wise.
+    // Names outside of the directory don't count
+    //
+    fn link_hardlinks(&mut self, hash_type: DVHashType, version: u8) -> Result<(), Error> {
+        let mut groups = BTreeMap::new();
+
+        self.inodes(&mut groups);
+
+        let mut leaders = BTreeMap::new();
+
+        for mut names in groups.into_values() {
+            if names.len() < 2 {
+                continue;
+            }
+
+            names.sort_by(|a, b| {
+                let a = a.as_os_str().as_encoded_bytes();
+                let b = b.as_os_str().as_encoded_bytes();
+                a.cmp(b)
+            });
+
+            for name in &names[1..] {
+                leaders.insert(name.to_path_buf(), names[0].to_path_buf());
+            }
+        }
+
+        if leaders.is_empty() {
+            return Ok(());
+        }
+
+        self.set_hardlinks(&leaders, hash_type, version)
+    }
+
+    fn compute_hash(&self, hash_type: DVHashType, version: u8) -> Result<Vec<u8>, Error> {
+        match version < MERKLE_FORMAT_VER {
+            true => self.legacy_hash(hash_type),
+            false => self.merkle_hash(hash_type),
+        }
+    }
+
+    //
+    // read_dir() order depends on the filesystem, the entries are sorted
+    // byte-wise so the same tree always produces the same manifest
+    //
+    fn read_entries(ctx: &WalkerContext, dir: &Path) -> Result<Vec<PathBuf>, Error> {
+        let mut entries = Vec::new();
+
+        match ctx.archive {
+            Some(tree) => {
+                let rel_dir = dir.strip_prefix(&ctx.directory)?;
+
+                for (path, _) in tree.children(rel_dir) {
+                    entries.push(ctx.directory.join(path));
                 }
             }
-            for entry in fs::read_dir(dir)? {
-                let entry = entry?.path();
+            None => {
+                if !dir.is_dir() {
+                    return Ok(entries);
+                }
+
+                for entry in fs::read_dir(dir)? {
+                    entries.push(entry?.path());
+                }
+            }
+        }
+
+        entries.sort_by(|a, b| {
+            let a = a.as_os_str().as_encoded_bytes();
+            let b = b.as_os_str().as_encoded_bytes();
+            a.cmp(b)
+        });
+
+        Ok(entries)
+    }
+
+    fn parse(
+        &mut self,
+        ctx: &WalkerContext,
+        dir: &Path,
+        ancestors: &[PathBuf],
+    ) -> Result<(), Error> {
+        let mut files = Vec::new();
+        let mut directories = Vec::new();
+        let mut symlinks = Vec::new();
+        let mut specials = Vec::new();
+        let mut subtrees = Vec::new();
+
+        for entry in WalkerDirectory::read_entries(ctx, dir)? {
+            let leads_to_scope = ctx.leads_to_scope(&entry);
+
+            if !leads_to_scope && !ctx.in_scope(&entry) {
+                continue;
+            }
+
+            let source = WalkerSource::new(ctx, &entry)?;
+
+            if ctx.exclude.is_excluded(&entry, source.is_dir()) {
+                continue;
+            }
+
+            let source = source.follow(ctx, &entry, ancestors)?;
 
-                if self.ignore_file(&entry) {
+            // nothing else can hold the path
+            if leads_to_scope && !source.is_dir() {
+                continue;
+            }
+
+            if source.is_dir() && ctx.subtrees.contains(&entry) {
+                subtrees.push(entry);
+            } else if source.is_dir() {
+                directories.push(entry);
+            } else if source.is_file() {
+                if ctx.is_signature(&entry) {
+                    info!("ignoring file={:?}", entry);
                     continue;
                 }
 
-                if entry.is_file() {
-                    let f = WalkerFile::new(root.as_ref(), entry, hash_type)?;
-                    self.files.push(f);
+                files.push((entry, source));
+            } else if source.is_symlink() {
+                if source.link_escapes(ctx, &entry)? {
+                    warn!("{:?} points outside of the directory", entry);
+                }
+
+                symlinks.push((entry, source));
+            } else {
+                match ctx.special_files {
+                    DVSpecialPolicy::Record => specials.push((entry, source)),
+                    DVSpecialPolicy::Skip => info!("skipping special file={:?}", entry),
+                    DVSpecialPolicy::Reject => {
+                        let err = format!("{:?} is not a file or directory", entry);
+                        return Err(Error::InvalidPath(err));
+                    }
                 }
             }
         }
 
+        self.specials = specials
+            .iter()
+            .map(|(s, source)| source.special(ctx, s))
+            .collect::<Result<_, Error>>()?;
+
+        self.subtrees = subtrees
+            .iter()
+            .map(|s| WalkerSubtree::new(&ctx.root, s))
+            .collect::<Result<_, Error>>()?;
+
+        self.symlinks = symlinks
+            .iter()
+            .map(|(l, source)| source.symlink(ctx, l))
+            .collect::<Result<_, Error>>()?;
+
+        //
+        // subdirectories and files are hashed on the thread pool, the
+        // results are collected in the sorted order
+        //
+        self.directories = directories
+            .par_iter()
+            .map(|d| WalkerDirectory::new_with_context(ctx, d, ancestors))
+            .collect::<Result<_, Error>>()?;
+
+        self.files = files
+            .par_iter()
+            .map(|(f, source)| source.file(ctx, f))
+            .collect::<Result<_, Error>>()?;
+
         Ok(())
     }
 
-    fn hash<T: Digest>(&self) -> Result<Vec<u8>, Error> {
-        let mut hash = T::new();
+    //
+    // every entry, file, directory, link, special file or signed subtree, adds
+    // its type, mode, name and hash in name order so the directory hash
+    // commits to the whole tree below it. The recorded attributes and xattrs
+    // follow, those of the directory itself come last
+    //
+    fn merkle_hash(&self, hash_type: DVHashType) -> Result<Vec<u8>, Error> {
+        let mut entries = Vec::new();
 
         for file in &self.files {
-            hash.update(&file.hash);
+            let mode = file.mode.unwrap_or_default();
+            let name = entry_name(&file.path)?;
+
+            let mut extra = file.attributes.encode();
+            extra.extend(encode_xattrs(&file.xattrs));
+
+            if let Some(hardlink) = &file.hardlink {
+                let hardlink = hardlink.as_os_str().as_encoded_bytes();
+                extra.push(MERKLE_HARDLINK);
+                extra.extend((hardlink.len() as u32).to_be_bytes());
+                extra.extend(hardlink);
+            }
+
+            entries.push((name, MERKLE_FILE, mode, &file.hash, extra));
         }
 
         for dir in &self.directories {
             if dir.hash.is_empty() {
                 return Err(Error::EmptyHash);
             }
+
+            let name = entry_name(&dir.directory)?;
+            // the attributes of a directory are in its own hash
+            entries.push((name, MERKLE_DIR, MERKLE_DIR_MODE, &dir.hash, vec![]));
         }
 
-        let digest = hash.finalize();
+        for link in &self.symlinks {
+            let name = entry_name(&link.path)?;
+            let extra = link.attributes.encode();
+            entries.push((name, MERKLE_LINK, MERKLE_LINK_MODE, &link.hash, extra));
+        }
 
-        Ok(digest.to_vec())
+        for special in &self.specials {
+            let name = entry_name(&special.path)?;
+            let extra = special.attributes.encode();
+            entries.push((
+                name,
+                MERKLE_SPECIAL,
+                MERKLE_SPECIAL_MODE,
+                &special.hash,
+                extra,
+            ));
+        }
+
+        // only the root hash of a subtree, its content has a signature of its own
+        for subtree in &self.subtrees {
+            let name = entry_name(&subtree.path)?;
+            entries.push((name, MERKLE_SUBTREE, MERKLE_DIR_MODE, &subtree.hash, vec![]));
+        }
+
+        entries.sort_by(|a, b| a.0.cmp(b.0));
+
+        let mut hash = DVHasher::new(hash_type);
+
+        for (name, entry_type, mode, entry_hash, attributes) in entries {
+            hash.update([entry_type]);
+            hash.update(mode.to_be_bytes());
+            hash.update((name.len() as u32).to_be_bytes());
+            hash.update(name);
+            hash.update((entry_hash.len() as u32).to_be_bytes());
+            hash.update(entry_hash);
+            hash.update(attributes);
+        }
+
+        hash.update(self.attributes.encode());
+        hash.update(encode_xattrs(&self.xattrs));
+
+        Ok(hash.finalize())
+    }
+
+    //
+    // format 3 and older only folded the hashes of the files
+    //
+    fn legacy_hash(&self, hash_type: DVHashType) -> Result<Vec<u8>, Error> {
+        let mut hash = DVHasher::new(hash_type);
+
+        for file in &self.files {
+            hash.update(&file.hash);
+        }
+
+        for dir in &self.directories {
+            if dir.hash.is_empty() {
+                return Err(Error::EmptyHash);
+            }
+        }
+
+        Ok(hash.finalize())
     }
 }
 
 #[cfg(test)]
 mod tests {
 
+    use std::os::unix::{
+        fs::{symlink, PermissionsExt},
+        net::UnixListener,
+    };
+
+    use crate::directory::special::DVSpecialKind;
+
+    use crate::common::testing::write_test_tree;
+
     use super::*;
 
+    fn root_hash(dir: &Path) -> Vec<u8> {
+        let root = WalkerDirectory::new(dir, DVHashType::Sha256, &WalkerHeader::default()).unwrap();
+        root.check_hash(DVHashType::Sha256, MERKLE_FORMAT_VER)
+            .unwrap();
+        root.hash
+    }
+
     #[test]
     fn walk_tmp() {
         let tmp_dir = tempfile::tempdir().unwrap().into_path();
@@ -173,6 +850,247 @@ mod tests {
         fs::create_dir_all(tmp_dir_1_2).unwrap();
         fs::create_dir_all(tmp_dir_3_4).unwrap();
 
-        WalkerDirectory::new(tmp_dir, DVHashType::Sha256).unwrap();
+        WalkerDirectory::new(tmp_dir, DVHashType::Sha256, &WalkerHeader::default()).unwrap();
+    }
+
+    #[test]
+    fn walk_exclude() {
+        let tmp_dir = tempfile::tempdir().unwrap().into_path();
+
+        fs::create_dir_all(tmp_dir.join("www").join("logs")).unwrap();
+        fs::write(tmp_dir.join("server.py"), "print()").unwrap();
+        fs::write(tmp_dir.join("server.pyc"), "garbage").unwrap();
+        fs::write(tmp_dir.join("www").join("app.pyc"), "garbage").unwrap();
+        fs::write(tmp_dir.join("www").join("keep.pyc"), "keep").unwrap();
+        fs::write(tmp_dir.join("www").join("logs").join("a.log"), "log").unwrap();
+
+        let header = WalkerHeader {
+            exclude: vec!["**/*.pyc".into(), "logs/".into(), "!keep.pyc".into()],
+            ..Default::default()
+        };
+
+        let root = WalkerDirectory::new(&tmp_dir, DVHashType::Sha256, &header).unwrap();
+
+        assert_eq!(root.files.len(), 1);
+        assert_eq!(root.directories.len(), 1);
+
+        let www = &root.directories[0];
+        assert_eq!(www.files.len(), 1);
+        assert!(www.files[0].path.ends_with("keep.pyc"));
+        assert!(www.directories.is_empty());
+    }
+
+    #[test]
+    fn walk_symlinks() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let product = tmp_dir.path().join("product");
+        write_test_tree(&product);
+
+        let www = product.join("www");
+        symlink("/etc", product.join("etc")).unwrap();
+        symlink("www", product.join("site")).unwrap();
+        symlink("missing", product.join("gone")).unwrap();
+
+        let record = WalkerHeader::default();
+        let root = WalkerDirectory::new(&product, DVHashType::Sha256, &record).unwrap();
+
+        let links: Vec<&Path> = root.symlinks.iter().map(|l| l.target.as_path()).collect();
+        assert_eq!(
+            links,
+            [Path::new("/etc"), Path::new("missing"), Path::new("www")]
+        );
+        assert_eq!(root.directories.len(), 1);
+
+        let follow = WalkerHeader {
+            follow_symlinks: true,
+            ..Default::default()
+        };
+
+        let walk = || WalkerDirectory::new(&product, DVHashType::Sha256, &follow);
+
+        // outside of the directory, dangling, then looping
+        assert!(matches!(walk(), Err(Error::InvalidPath(_))));
+        fs::remove_file(product.join("etc")).unwrap();
+        assert!(matches!(walk(), Err(Error::InvalidPath(_))));
+        fs::remove_file(product.join("gone")).unwrap();
+
+        symlink("..", www.join("js").join("back")).unwrap();
+        assert!(matches!(walk(), Err(Error::InvalidPath(_))));
+        fs::remove_file(www.join("js").join("back")).unwrap();
+
+        let root = walk().unwrap();
+        assert!(root.symlinks.is_empty());
+
+        let names: Vec<&Path> = root
+            .directories
+            .iter()
+            .map(|d| d.directory.as_path())
+            .collect();
+        assert_eq!(names, [Path::new("product/site"), Path::new("product/www")]);
+        assert_eq!(root.directories[0].hash, root.directories[1].hash);
+    }
+
+    #[test]
+    fn walk_excluded_symlinks() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let product = tmp_dir.path().join("product");
+        write_test_tree(&product);
+
+        symlink("missing", product.join("gone")).unwrap();
+        symlink("..", product.join("www").join("back")).unwrap();
+        symlink("/etc", product.join("etc")).unwrap();
+
+        // an excluded link is never followed, wherever it points
+        let header = WalkerHeader {
+            follow_symlinks: true,
+            exclude: vec!["gone".into(), "back".into(), "/etc".into()],
+            ..Default::default()
+        };
+        let root = WalkerDirectory::new(&product, DVHashType::Sha256, &header).unwrap();
+
+        assert!(root.symlinks.is_empty());
+        assert!(!root.contains(Path::new("product/gone")));
+        assert!(!root.contains(Path::new("product/www/back")));
+        assert!(!root.contains(Path::new("product/etc")));
+    }
+
+    #[test]
+    fn walk_special_files() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let product = tmp_dir.path().join("product");
+        write_test_tree(&product);
+
+        let _listener = UnixListener::bind(product.join("app.sock")).unwrap();
+
+        let walk = |special_files| {
+            let header = WalkerHeader {
+                special_files,
+                ..Default::default()
+            };
+            WalkerDirectory::new(&product, DVHashType::Sha256, &header)
+        };
+
+        let ret = walk(DVSpecialPolicy::Reject);
+        assert!(matches!(ret, Err(Error::InvalidPath(_))));
+
+        let skipped = walk(DVSpecialPolicy::Skip).unwrap();
+        assert!(skipped.specials.is_empty());
+
+        let recorded = walk(DVSpecialPolicy::Record).unwrap();
+        assert_eq!(recorded.specials.len(), 1);
+        assert_eq!(recorded.specials[0].path, Path::new("product/app.sock"));
+        assert_eq!(recorded.specials[0].kind, DVSpecialKind::Socket);
+        assert_ne!(recorded.hash, skipped.hash);
+        assert!(recorded.contains(Path::new("product/app.sock")));
+
+        // an empty directory is an entry of its own
+        fs::create_dir(product.join("empty")).unwrap();
+        let with_empty = walk(DVSpecialPolicy::Skip).unwrap();
+        assert_ne!(with_empty.hash, skipped.hash);
+        assert!(with_empty.contains(Path::new("product/empty")));
+    }
+
+    #[test]
+    fn walk_hardlinks() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let product = tmp_dir.path().join("product");
+        write_test_tree(&product);
+
+        let www = product.join("www");
+        let single = root_hash(&product);
+
+        fs::copy(product.join("server.py"), www.join("server.py")).unwrap();
+        let copied = root_hash(&product);
+
+        // same content, but sharing the inode
+        fs::remove_file(www.join("server.py")).unwrap();
+        fs::hard_link(product.join("server.py"), www.join("server.py")).unwrap();
+        fs::hard_link(product.join("server.py"), product.join("main.py")).unwrap();
+
+        let header = WalkerHeader::default();
+        let root = WalkerDirectory::new(&product, DVHashType::Sha256, &header).unwrap();
+        root.check_hash(DVHashType::Sha256, header.version).unwrap();
+        assert_ne!(root.hash, copied);
+        assert_ne!(root.hash, single);
+
+        // the byte-wise first name leads the group
+        let main = root
+            .files
+            .iter()
+            .find(|f| f.path.ends_with("main.py"))
+            .unwrap();
+        assert!(main.hardlink.is_none());
+
+        let server = root
+            .files
+            .iter()
+            .find(|f| f.path.ends_with("server.py"))
+            .unwrap();
+        assert_eq!(
+            server.hardlink.as_deref(),
+            Some(Path::new("product/main.py"))
+        );
+
+        let www = &root.directories[0];
+        let server = www
+            .files
+            .iter()
+            .find(|f| f.path.ends_with("server.py"))
+            .unwrap();
+        assert_eq!(
+            server.hardlink.as_deref(),
+            Some(Path::new("product/main.py"))
+        );
+
+        // older formats don't know about them
+        let legacy = WalkerHeader {
+            version: MERKLE_FORMAT_VER,
+            ..Default::default()
+        };
+        let root = WalkerDirectory::new(&product, DVHashType::Sha256, &legacy).unwrap();
+        assert!(root.files.iter().all(|f| f.hardlink.is_none()));
+    }
+
+    #[test]
+    fn merkle_hash() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let product = tmp_dir.path().join("product");
+        write_test_tree(&product);
+
+        let mut hashes = vec![root_hash(&product)];
+        assert_eq!(root_hash(&product), hashes[0]);
+
+        // same content under another name
+        let www = product.join("www");
+        fs::rename(www.join("index.html"), www.join("main.html")).unwrap();
+        hashes.push(root_hash(&product));
+
+        // moved one level down
+        fs::rename(www.join("main.html"), www.join("js").join("main.html")).unwrap();
+        hashes.push(root_hash(&product));
+
+        let app = www.join("js").join("app.js");
+        fs::set_permissions(&app, fs::Permissions::from_mode(0o755)).unwrap();
+        hashes.push(root_hash(&product));
+
+        // only the executable bit matters
+        fs::set_permissions(&app, fs::Permissions::from_mode(0o700)).unwrap();
+        assert_eq!(root_hash(&product), hashes[3]);
+
+        // a file replaced by an empty directory
+        fs::remove_file(product.join("server.py")).unwrap();
+        fs::create_dir(product.join("server.py")).unwrap();
+        hashes.push(root_hash(&product));
+
+        for (i, a) in hashes.iter().enumerate() {
+            assert!(hashes[i + 1..].iter().all(|b| a != b));
+        }
+
+        let header = WalkerHeader::default();
+        let mut root = WalkerDirectory::new(&product, DVHashType::Sha256, &header).unwrap();
+        root.directories[1].directories[0].files[0].hash[0] ^= 1;
+
+        let ret = root.check_hash(DVHashType::Sha256, MERKLE_FORMAT_VER);
+        assert!(matches!(ret, Err(Error::VerificationFailure)));
     }
 }
@@ -0,0 +1,80 @@
+use std::path::Path;
+
+use ignore::{
+    gitignore::{Gitignore, GitignoreBuilder},
+    Match,
+};
+use log::info;
+
+use crate::error::Result;
+
+//
+// gitignore style patterns, anchored at the signed directory
+//
+// **/*.pyc     any .pyc file at any depth
+// logs/        any directory named logs
+// /build       only the build entry at the root
+// !keep.pyc    re-include something a previous pattern excluded
+//
+#[derive(Debug)]
+pub struct ExcludeFilter {
+    matcher: Gitignore,
+}
+
+impl ExcludeFilter {
+    pub fn new<P: AsRef<Path>>(directory: P, patterns: &[String]) -> Result<ExcludeFilter> {
+        let mut builder = GitignoreBuilder::new(directory);
+
+        for pattern in patterns {
+            builder.add_line(None, pattern)?;
+        }
+
+        let matcher = builder.build()?;
+
+        Ok(ExcludeFilter { matcher })
+    }
+
+    pub fn is_excluded<P: AsRef<Path>>(&self, path: P, is_dir: bool) -> bool {
+        match self.matcher.matched(&path, is_dir) {
+            Match::Ignore(glob) => {
+                info!("excluding {:?} ({})", path.as_ref(), glob.original());
+                true
+            }
+            Match::Whitelist(_) | Match::None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn filter(patterns: &[&str]) -> ExcludeFilter {
+        let patterns: Vec<String> = patterns.iter().map(|p| p.to_string()).collect();
+        ExcludeFilter::new("/product", &patterns).unwrap()
+    }
+
+    #[test]
+    fn exclude_globs() {
+        let f = filter(&["**/*.pyc", "logs/", "!keep.pyc", "/build"]);
+
+        assert!(f.is_excluded("/product/a.pyc", false));
+        assert!(f.is_excluded("/product/www/js/a.pyc", false));
+        assert!(!f.is_excluded("/product/www/keep.pyc", false));
+        assert!(!f.is_excluded("/product/server.py", false));
+
+        assert!(f.is_excluded("/product/logs", true));
+        assert!(f.is_excluded("/product/www/logs", true));
+        assert!(!f.is_excluded("/product/logs", false));
+
+        assert!(f.is_excluded("/product/build", true));
+        assert!(!f.is_excluded("/product/www/build", true));
+    }
+
+    #[test]
+    fn exclude_invalid() {
+        let patterns = vec!["{a,b".to_string()];
+        assert!(ExcludeFilter::new("/product", &patterns).is_err());
+    }
+}
@@ -1,21 +1,53 @@
 #![allow(unused)]
 use core::fmt;
-use std::path::{Path, PathBuf};
+use std::{
+    fs,
+    os::unix::fs::{MetadataExt, PermissionsExt},
+    path::{Path, PathBuf},
+};
 
-use serde_derive::Serialize;
+use serde_derive::{Deserialize, Serialize};
 
 use crate::common::hash::{hash_file, DVHashType};
-use crate::common::serializer::hex_serializer;
+use crate::common::serializer::{hex_deserializer, hex_serializer};
 
 use crate::error::Error;
 
-#[derive(Debug, Serialize)]
+use super::{
+    attributes::{DVAttribute, WalkerAttributes},
+    cache::HashCache,
+    xattrs::{read_xattrs, WalkerXattr},
+};
+
+#[derive(Debug, Serialize, Deserialize)]
 pub struct WalkerFile {
     pub path: PathBuf,
-    #[serde(serialize_with = "hex_serializer")]
+    #[serde(
+        serialize_with = "hex_serializer",
+        deserialize_with = "hex_deserializer"
+    )]
     pub hash: Vec<u8>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mode: Option<u32>,
+    #[serde(flatten)]
+    pub attributes: WalkerAttributes,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub xattrs: Vec<WalkerXattr>,
+    // the first name, byte-wise, of the files sharing this inode
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hardlink: Option<PathBuf>,
+    // device and inode while walking, only for files with several names
+    #[serde(skip)]
+    pub inode: Option<(u64, u64)>,
 }
 
+//
+// like git only the executable bit is kept, umask differences between hosts
+// don't show up as changes
+//
+const MODE_EXECUTABLE: u32 = 0o755;
+const MODE_REGULAR: u32 = 0o644;
+
 impl fmt::Display for WalkerFile {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         let hash_str = hex::encode(&self.hash);
@@ -28,14 +60,57 @@ impl WalkerFile {
         root: P,
         file: T,
         hash_type: DVHashType,
+        cache: Option<&HashCache>,
+        attributes: &[DVAttribute],
+        xattrs: &[String],
     ) -> Result<WalkerFile, Error> {
         let rel_name = file.as_ref().strip_prefix(&root)?;
 
-        let hash = hash_file(&file, hash_type)?;
+        let metadata = fs::metadata(&file)?;
+
+        let hash = match cache {
+            Some(cache) => cache.hash_file(&file, &metadata, hash_type)?,
+            None => hash_file(&file, hash_type)?,
+        };
+
+        let mut f = WalkerFile::with_hash(
+            rel_name,
+            hash,
+            metadata.permissions().mode(),
+            WalkerAttributes::new(&metadata, attributes),
+            read_xattrs(&file, xattrs)?,
+        );
+
+        if metadata.nlink() > 1 {
+            f.inode = Some((metadata.dev(), metadata.ino()));
+        }
+
+        Ok(f)
+    }
+
+    //
+    // for content that was hashed somewhere else, an archive entry
+    //
+    pub fn with_hash<P: AsRef<Path>>(
+        path: P,
+        hash: Vec<u8>,
+        permissions: u32,
+        attributes: WalkerAttributes,
+        xattrs: Vec<WalkerXattr>,
+    ) -> WalkerFile {
+        let mode = match permissions & 0o111 {
+            0 => MODE_REGULAR,
+            _ => MODE_EXECUTABLE,
+        };
 
-        Ok(WalkerFile {
-            path: rel_name.into(),
+        WalkerFile {
+            path: path.as_ref().into(),
             hash,
-        })
+            mode: Some(mode),
+            attributes,
+            xattrs,
+            hardlink: None,
+            inode: None,
+        }
     }
 }
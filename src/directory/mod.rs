@@ -1,3 +1,13 @@
+pub mod archive;
+pub mod attributes;
+pub mod cache;
+mod context;
+pub mod diff;
 mod dir;
+mod exclude;
 mod file;
+pub mod special;
+pub mod subtree;
+pub mod symlink;
 pub mod walker;
+pub mod xattrs;
@@ -0,0 +1,170 @@
+use core::fmt;
+use std::{
+    fs::Metadata,
+    os::unix::fs::{FileTypeExt, MetadataExt},
+    path::{Path, PathBuf},
+};
+
+use serde_derive::{Deserialize, Serialize};
+
+use crate::{
+    common::{
+        hash::{hash_string, DVHashType},
+        serializer::{hex_deserializer, hex_serializer},
+    },
+    error::{Error, Result},
+};
+
+use super::attributes::{DVAttribute, WalkerAttributes};
+
+//
+// what to do with sockets, fifos and device nodes. Rejecting them is what
+// older releases did
+//
+#[derive(Debug, Copy, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DVSpecialPolicy {
+    Record,
+    Skip,
+    #[default]
+    Reject,
+}
+
+impl DVSpecialPolicy {
+    pub fn is_reject(&self) -> bool {
+        *self == DVSpecialPolicy::Reject
+    }
+}
+
+impl fmt::Display for DVSpecialPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DVSpecialPolicy::Record => write!(f, "record"),
+            DVSpecialPolicy::Skip => write!(f, "skip"),
+            DVSpecialPolicy::Reject => write!(f, "reject"),
+        }
+    }
+}
+
+impl std::str::FromStr for DVSpecialPolicy {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "record" => Ok(DVSpecialPolicy::Record),
+            "skip" => Ok(DVSpecialPolicy::Skip),
+            "reject" => Ok(DVSpecialPolicy::Reject),
+            _ => Err(Error::InvalidArgument(format!(
+                "unknown special file policy {s}"
+            ))),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DVSpecialKind {
+    Fifo,
+    Socket,
+    Char,
+    Block,
+}
+
+impl fmt::Display for DVSpecialKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DVSpecialKind::Fifo => write!(f, "fifo"),
+            DVSpecialKind::Socket => write!(f, "socket"),
+            DVSpecialKind::Char => write!(f, "char"),
+            DVSpecialKind::Block => write!(f, "block"),
+        }
+    }
+}
+
+impl DVSpecialKind {
+    pub fn from_metadata(metadata: &Metadata) -> Option<DVSpecialKind> {
+        let file_type = metadata.file_type();
+
+        if file_type.is_fifo() {
+            Some(DVSpecialKind::Fifo)
+        } else if file_type.is_socket() {
+            Some(DVSpecialKind::Socket)
+        } else if file_type.is_char_device() {
+            Some(DVSpecialKind::Char)
+        } else if file_type.is_block_device() {
+            Some(DVSpecialKind::Block)
+        } else {
+            None
+        }
+    }
+}
+
+//
+// there is no content to hash, the kind and for devices the device number are
+//
+#[derive(Debug, Serialize, Deserialize)]
+pub struct WalkerSpecial {
+    pub path: PathBuf,
+    pub kind: DVSpecialKind,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub device: Option<u64>,
+    #[serde(
+        serialize_with = "hex_serializer",
+        deserialize_with = "hex_deserializer"
+    )]
+    pub hash: Vec<u8>,
+    #[serde(flatten)]
+    pub attributes: WalkerAttributes,
+}
+
+impl WalkerSpecial {
+    pub fn new<P: AsRef<Path>, T: AsRef<Path>>(
+        root: P,
+        entry: T,
+        metadata: &Metadata,
+        hash_type: DVHashType,
+        attributes: &[DVAttribute],
+    ) -> Result<WalkerSpecial> {
+        let rel_name = entry.as_ref().strip_prefix(&root)?;
+
+        let kind = match DVSpecialKind::from_metadata(metadata) {
+            Some(v) => v,
+            None => {
+                let err = format!("{:?} is not a special file", entry.as_ref());
+                return Err(Error::InvalidPath(err));
+            }
+        };
+
+        let device = match kind {
+            DVSpecialKind::Char | DVSpecialKind::Block => Some(metadata.rdev()),
+            _ => None,
+        };
+
+        let attributes = WalkerAttributes::new(metadata, attributes);
+
+        Ok(WalkerSpecial::with_kind(
+            rel_name, kind, device, hash_type, attributes,
+        ))
+    }
+
+    pub fn with_kind<P: AsRef<Path>>(
+        path: P,
+        kind: DVSpecialKind,
+        device: Option<u64>,
+        hash_type: DVHashType,
+        attributes: WalkerAttributes,
+    ) -> WalkerSpecial {
+        let hash = match device {
+            Some(v) => hash_string(&format!("{kind}:{v}"), hash_type),
+            None => hash_string(&kind.to_string(), hash_type),
+        };
+
+        WalkerSpecial {
+            path: path.as_ref().into(),
+            kind,
+            device,
+            hash,
+            attributes,
+        }
+    }
+}
@@ -0,0 +1,62 @@
+use core::fmt;
+use std::path::{Path, PathBuf};
+
+use serde_derive::{Deserialize, Serialize};
+
+use crate::{
+    common::{
+        r#const::DEFAULT_SIGN_FILE_NAME,
+        serializer::{hex_deserializer, hex_serializer},
+    },
+    error::{Error, Result},
+    sign::sign_dir::DVSignature,
+};
+
+use super::walker::Walker;
+
+//
+// a subdirectory signed on its own, by a vendor for instance. The parent only
+// records the root hash from the subtree's signature, the content is checked
+// against the subtree's manifest with the subtree's key
+//
+#[derive(Debug, Serialize, Deserialize)]
+pub struct WalkerSubtree {
+    pub path: PathBuf,
+    #[serde(
+        serialize_with = "hex_serializer",
+        deserialize_with = "hex_deserializer"
+    )]
+    pub hash: Vec<u8>,
+}
+
+impl fmt::Display for WalkerSubtree {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?} hash={}", self.path, hex::encode(&self.hash))
+    }
+}
+
+impl WalkerSubtree {
+    pub fn new<P: AsRef<Path>, T: AsRef<Path>>(root: P, dir: T) -> Result<WalkerSubtree> {
+        let dir = dir.as_ref();
+        let rel_name = dir.strip_prefix(&root)?;
+
+        // the signature isn't trusted here, only when the subtree is verified
+        let s = DVSignature::from_file(dir.join(DEFAULT_SIGN_FILE_NAME))?;
+
+        let hash = match s.content().is_empty() {
+            true => None,
+            false => Walker::decode(s.content())?.root_hash().map(|h| h.to_vec()),
+        };
+
+        match hash {
+            Some(hash) => Ok(WalkerSubtree {
+                path: rel_name.into(),
+                hash,
+            }),
+            None => {
+                let err = format!("the {:?} subtree signature has no root hash", dir);
+                Err(Error::InvalidArgument(err))
+            }
+        }
+    }
+}
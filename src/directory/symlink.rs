@@ -0,0 +1,151 @@
+use core::fmt;
+use std::{
+    fs,
+    path::{Component, Path, PathBuf},
+};
+
+use serde_derive::{Deserialize, Serialize};
+
+use crate::{
+    common::{
+        hash::{hash_data, DVHashType},
+        serializer::{hex_deserializer, hex_serializer},
+    },
+    error::Error,
+};
+
+use super::attributes::{DVAttribute, WalkerAttributes};
+
+//
+// a link is recorded as a link, only the target string is hashed. What it
+// points to isn't part of the tree
+//
+#[derive(Debug, Serialize, Deserialize)]
+pub struct WalkerSymlink {
+    pub path: PathBuf,
+    pub target: PathBuf,
+    #[serde(
+        serialize_with = "hex_serializer",
+        deserialize_with = "hex_deserializer"
+    )]
+    pub hash: Vec<u8>,
+    #[serde(flatten)]
+    pub attributes: WalkerAttributes,
+}
+
+impl fmt::Display for WalkerSymlink {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?} -> {:?}", self.path, self.target)
+    }
+}
+
+impl WalkerSymlink {
+    pub fn new<P: AsRef<Path>, T: AsRef<Path>>(
+        root: P,
+        link: T,
+        hash_type: DVHashType,
+        attributes: &[DVAttribute],
+    ) -> Result<WalkerSymlink, Error> {
+        let rel_name = link.as_ref().strip_prefix(&root)?;
+
+        let target = fs::read_link(&link)?;
+
+        // the link itself, not what it points to
+        let metadata = fs::symlink_metadata(&link)?;
+        let attributes = WalkerAttributes::new(&metadata, attributes);
+
+        Ok(WalkerSymlink::with_target(
+            rel_name, target, hash_type, attributes,
+        ))
+    }
+
+    pub fn with_target<P: AsRef<Path>>(
+        path: P,
+        target: PathBuf,
+        hash_type: DVHashType,
+        attributes: WalkerAttributes,
+    ) -> WalkerSymlink {
+        let hash = hash_data(target.as_os_str().as_encoded_bytes(), hash_type);
+
+        WalkerSymlink {
+            path: path.as_ref().into(),
+            target,
+            hash,
+            attributes,
+        }
+    }
+}
+
+//
+// resolves the target without touching the filesystem, a dangling link can
+// still be told to point outside of the directory
+//
+pub fn link_escapes<P: AsRef<Path>, T: AsRef<Path>>(directory: P, link: T) -> Result<bool, Error> {
+    let target = fs::read_link(&link)?;
+
+    Ok(target_escapes(directory, link, &target))
+}
+
+//
+// same as link_escapes() for a link that isn't on the filesystem
+//
+pub fn target_escapes<P: AsRef<Path>, T: AsRef<Path>>(
+    directory: P,
+    link: T,
+    target: &Path,
+) -> bool {
+    let mut resolved = match link.as_ref().parent() {
+        Some(v) => v.to_path_buf(),
+        None => return true,
+    };
+
+    for component in target.components() {
+        match component {
+            Component::RootDir | Component::Prefix(_) => resolved = PathBuf::from("/"),
+            Component::ParentDir => {
+                resolved.pop();
+            }
+            Component::CurDir => {}
+            Component::Normal(v) => resolved.push(v),
+        }
+    }
+
+    !resolved.starts_with(directory)
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::os::unix::fs::symlink;
+
+    use super::*;
+
+    #[test]
+    fn symlink_escapes() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let product = tmp_dir.path().join("product");
+        let www = product.join("www");
+        fs::create_dir_all(&www).unwrap();
+
+        let links = [
+            ("up", "../index.html", false),
+            ("missing", "../../missing", true),
+            ("etc", "/etc", true),
+            ("abs", product.to_str().unwrap(), false),
+            ("dot", "./a/../../../product/x", false),
+        ];
+
+        for (name, target, escapes) in links {
+            let link = www.join(name);
+            symlink(target, &link).unwrap();
+            assert_eq!(link_escapes(&product, &link).unwrap(), escapes, "{name}");
+        }
+
+        let link = WalkerSymlink::new(tmp_dir.path(), www.join("etc"), DVHashType::Sha256, &[]);
+        let link = link.unwrap();
+        assert_eq!(link.path, Path::new("product/www/etc"));
+        assert_eq!(link.target, Path::new("/etc"));
+        assert_eq!(link.hash, hash_data(b"/etc", DVHashType::Sha256));
+        assert!(link.attributes.is_empty());
+    }
+}
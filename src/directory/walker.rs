@@ -1,31 +1,438 @@
-use std::path::Path;
+use std::path::{Component, Path, PathBuf};
 
 use base64::{prelude::BASE64_STANDARD, Engine};
-use serde_derive::Serialize;
+use rayon::ThreadPoolBuilder;
+use serde_derive::{Deserialize, Serialize};
 
-use crate::{common::hash::DVHashType, error::Result};
+use crate::{
+    common::{
+        hash::DVHashType,
+        serializer::{hex_option_deserializer, hex_option_serializer},
+    },
+    error::{Error, Result},
+};
 
-use super::dir::WalkerDirectory;
-const CUR_SIG_FORMAT_VER: u8 = 1;
+use super::{
+    archive::is_archive,
+    attributes::DVAttribute,
+    cache::HashCache,
+    diff::{diff_directories, WalkerChange, WalkerChangeKind},
+    dir::WalkerDirectory,
+    special::DVSpecialPolicy,
+};
+const CUR_SIG_FORMAT_VER: u8 = 12;
 
-#[derive(Debug, Serialize)]
+// first format where directory hashes cover the whole subtree
+pub const MERKLE_FORMAT_VER: u8 = 4;
+
+// first format recording symlinks instead of following them
+pub const SYMLINK_FORMAT_VER: u8 = 6;
+
+// first format recording hardlinks and special files
+pub const HARDLINK_FORMAT_VER: u8 = 7;
+
+// first format only skipping the signature file it was made with
+pub const SIGNATURE_FORMAT_VER: u8 = 9;
+
+// first format where short signatures cover the header and the root hash
+pub const ROOT_HASH_FORMAT_VER: u8 = 12;
+
+//
+// everything needed to rebuild the manifest from the directory itself
+//
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WalkerHeader {
+    pub version: u8,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hash_type: Option<DVHashType>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created: Option<u64>,
+    // the signature is only valid in between, unix times
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub not_before: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_version: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signer: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub exclude: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub attributes: Vec<DVAttribute>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub xattrs: Vec<String>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub follow_symlinks: bool,
+    #[serde(default, skip_serializing_if = "DVSpecialPolicy::is_reject")]
+    pub special_files: DVSpecialPolicy,
+    // signature file relative to the directory, when it sits inside it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signature: Option<PathBuf>,
+    // subdirectories signed on their own, relative to the directory
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub subtrees: Vec<PathBuf>,
+}
+
+impl Default for WalkerHeader {
+    fn default() -> Self {
+        WalkerHeader {
+            version: CUR_SIG_FORMAT_VER,
+            hash_type: None,
+            created: None,
+            not_before: None,
+            expires: None,
+            tool_version: None,
+            signer: None,
+            exclude: vec![],
+            attributes: vec![],
+            xattrs: vec![],
+            follow_symlinks: false,
+            special_files: DVSpecialPolicy::Reject,
+            signature: None,
+            subtrees: vec![],
+        }
+    }
+}
+
+//
+// paths given by the user have to stay inside the directory
+//
+pub fn normalize_path(path: &Path) -> Result<PathBuf> {
+    let mut normalized = PathBuf::new();
+
+    for component in path.components() {
+        match component {
+            Component::Normal(v) => normalized.push(v),
+            Component::CurDir => {}
+            _ => {
+                let msg = format!("{} isn't relative to the directory", path.display());
+                return Err(Error::InvalidArgument(msg));
+            }
+        }
+    }
+
+    Ok(normalized)
+}
+
+#[derive(Debug, Serialize, Deserialize)]
 pub struct Walker {
-    version: u8,
-    root: WalkerDirectory,
+    #[serde(flatten)]
+    pub header: WalkerHeader,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    root: Option<WalkerDirectory>,
+    // what a short manifest keeps of the tree, it's rebuilt from the directory
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "hex_option_serializer",
+        deserialize_with = "hex_option_deserializer"
+    )]
+    root_hash: Option<Vec<u8>>,
+    // hashing threads, 0 for one per core. Not part of the manifest
+    #[serde(skip)]
+    pub jobs: usize,
+    // hashes of the previous walk, only when signing
+    #[serde(skip)]
+    pub cache: Option<HashCache>,
 }
 
 impl Walker {
-    pub fn new<P: AsRef<Path>>(directory: P, hash: DVHashType) -> Result<Walker> {
-        let root = WalkerDirectory::new(directory, hash)?;
+    pub fn new<P: AsRef<Path>>(
+        directory: P,
+        hash: DVHashType,
+        exclude: Vec<String>,
+    ) -> Result<Walker> {
+        let header = WalkerHeader {
+            hash_type: Some(hash),
+            exclude,
+            ..Default::default()
+        };
 
-        Ok(Walker {
-            version: CUR_SIG_FORMAT_VER,
-            root,
+        let mut walker = Walker::with_header(header);
+        walker.walk(directory, hash)?;
+
+        Ok(walker)
+    }
+
+    pub fn with_header(header: WalkerHeader) -> Walker {
+        Walker {
+            header,
+            root: None,
+            root_hash: None,
+            jobs: 0,
+            cache: None,
+        }
+    }
+
+    pub fn decode(data: &str) -> Result<Walker> {
+        let json_data = BASE64_STANDARD.decode(data)?;
+        let walker: Walker = serde_json::from_slice(&json_data)?;
+
+        if walker.header.version > CUR_SIG_FORMAT_VER {
+            return Err(Error::UnsupportedFormatVersion(walker.header.version));
+        }
+
+        Ok(walker)
+    }
+
+    //
+    // the work stealing pool only changes who hashes what, entries are
+    // collected in order and the manifest is the same for any number of jobs
+    //
+    fn install<T, F>(&self, f: F) -> Result<T>
+    where
+        T: Send,
+        F: FnOnce() -> Result<T> + Send,
+    {
+        let pool = ThreadPoolBuilder::new().num_threads(self.jobs).build()?;
+        pool.install(f)
+    }
+
+    //
+    // an archive is read from start to end, its entries are then walked like
+    // those of a directory
+    //
+    fn read(
+        &self,
+        directory: &Path,
+        hash: DVHashType,
+        scope: Option<&Path>,
+    ) -> Result<WalkerDirectory> {
+        self.install(|| match is_archive(directory) {
+            true => WalkerDirectory::from_archive(directory, hash, &self.header, scope),
+            false => {
+                let cache = self.cache.as_ref();
+                WalkerDirectory::new_with_scope(directory, hash, &self.header, scope, cache)
+            }
         })
     }
 
+    pub fn walk<P: AsRef<Path>>(&mut self, directory: P, hash: DVHashType) -> Result<()> {
+        let root = self.read(directory.as_ref(), hash, None)?;
+        self.root = Some(root);
+        self.root_hash = None;
+        Ok(())
+    }
+
     pub fn encode(&self) -> Result<String> {
         let json_string = serde_json::to_string(self)?;
         Ok(BASE64_STANDARD.encode(json_string))
     }
+
+    pub fn encode_header(&self) -> Result<String> {
+        let mut walker = Walker::with_header(self.header.clone());
+        walker.root_hash = self.root_hash().map(|h| h.to_vec());
+        walker.encode()
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.root.is_some()
+    }
+
+    pub fn root_hash(&self) -> Option<&[u8]> {
+        match &self.root {
+            Some(root) => Some(root.hash()),
+            None => self.root_hash.as_deref(),
+        }
+    }
+
+    //
+    // signature files recorded as content, they are neither checked nor
+    // trusted
+    //
+    pub fn signature_files(&self) -> Vec<&Path> {
+        match &self.root {
+            Some(root) => root.signature_files(),
+            None => vec![],
+        }
+    }
+
+    pub fn check_hash(&self, hash: DVHashType) -> Result<()> {
+        match &self.root {
+            Some(root) => root.check_hash(hash, self.header.version),
+            None => Ok(()),
+        }
+    }
+
+    //
+    // only keeps the given attributes, the directory hashes are recomputed so
+    // the manifest can be compared with a walk recording just those
+    //
+    pub fn retain_attributes(
+        &mut self,
+        attributes: &[DVAttribute],
+        hash: DVHashType,
+    ) -> Result<()> {
+        self.header.attributes.retain(|a| attributes.contains(a));
+
+        if let Some(root) = &mut self.root {
+            root.retain_attributes(attributes, hash, self.header.version)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn diff(&self, current: &Walker) -> Vec<WalkerChange> {
+        diff_directories(self.root.as_ref(), current.root.as_ref())
+    }
+
+    //
+    // compares a single entry, relative to the directory, with the manifest.
+    // Only what leads to it and what's below it gets walked and hashed
+    //
+    pub fn diff_path<P: AsRef<Path>>(
+        &self,
+        directory: P,
+        hash: DVHashType,
+        path: &Path,
+    ) -> Result<Vec<WalkerChange>> {
+        let root = match &self.root {
+            Some(v) => v,
+            None => return Err(Error::InvalidArgument("the manifest is empty".into())),
+        };
+
+        let path = normalize_path(path)?;
+
+        let current = self.read(directory.as_ref(), hash, Some(&path))?;
+
+        let prefix = root.directory.join(&path);
+
+        // the other names of an inode may be outside of the scope
+        let changes: Vec<WalkerChange> = diff_directories(Some(root), Some(&current))
+            .into_iter()
+            .filter(|c| c.path.starts_with(&prefix) && c.kind != WalkerChangeKind::Hardlinks)
+            .collect();
+
+        if changes.is_empty() && !root.contains(&prefix) {
+            let msg = format!("{} isn't in the manifest", path.display());
+            return Err(Error::NotFound(msg));
+        }
+
+        Ok(changes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::fs;
+
+    use crate::common::testing::write_test_tree;
+
+    use super::*;
+
+    #[test]
+    fn walker_header_rebuild() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let product = tmp_dir.path().join("product");
+        write_test_tree(&product);
+        fs::write(product.join("server.pyc"), "compiled").unwrap();
+
+        let exclude = vec!["*.pyc".to_string()];
+        let walker = Walker::new(&product, DVHashType::Sha256, exclude.clone()).unwrap();
+        let encoded = walker.encode().unwrap();
+
+        fs::write(product.join("server.pyc"), "recompiled").unwrap();
+
+        let mut rebuilt = Walker::decode(&walker.encode_header().unwrap()).unwrap();
+        assert_eq!(rebuilt.header.exclude, exclude);
+        assert!(rebuilt.root.is_none());
+
+        rebuilt.walk(&product, DVHashType::Sha256).unwrap();
+        assert_eq!(rebuilt.encode().unwrap(), encoded);
+
+        let complete = Walker::decode(&encoded).unwrap();
+        assert_eq!(complete.encode().unwrap(), encoded);
+    }
+
+    #[test]
+    fn walker_future_version() {
+        let header = WalkerHeader {
+            version: CUR_SIG_FORMAT_VER + 1,
+            ..Default::default()
+        };
+
+        let encoded = Walker::with_header(header).encode().unwrap();
+
+        let ret = Walker::decode(&encoded);
+        assert!(matches!(ret, Err(Error::UnsupportedFormatVersion(_))));
+    }
+
+    #[test]
+    fn walker_legacy_version() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let product = tmp_dir.path().join("product");
+        write_test_tree(&product);
+
+        let header = WalkerHeader {
+            version: MERKLE_FORMAT_VER - 1,
+            ..Default::default()
+        };
+
+        let mut legacy = Walker::with_header(header);
+        legacy.walk(&product, DVHashType::Sha256).unwrap();
+        legacy.check_hash(DVHashType::Sha256).unwrap();
+
+        let json = BASE64_STANDARD.decode(legacy.encode().unwrap()).unwrap();
+        assert!(!String::from_utf8(json).unwrap().contains("\"mode\""));
+
+        let current = Walker::new(&product, DVHashType::Sha256, vec![]).unwrap();
+        assert_ne!(legacy.root_hash(), current.root_hash());
+    }
+
+    #[test]
+    fn walker_diff_path() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let product = tmp_dir.path().join("product");
+        write_test_tree(&product);
+
+        let signed = Walker::new(&product, DVHashType::Sha256, vec![]).unwrap();
+
+        fs::write(product.join("www").join("js").join("app.js"), "changed").unwrap();
+        fs::write(product.join("server.py"), "changed").unwrap();
+
+        let changes = signed
+            .diff_path(&product, DVHashType::Sha256, Path::new("www"))
+            .unwrap();
+        let paths: Vec<&Path> = changes.iter().map(|c| c.path.as_path()).collect();
+        assert_eq!(paths, [Path::new("product/www/js/app.js")]);
+
+        let changes = signed
+            .diff_path(&product, DVHashType::Sha256, Path::new("www/index.html"))
+            .unwrap();
+        assert!(changes.is_empty());
+
+        let ret = signed.diff_path(&product, DVHashType::Sha256, Path::new("www/none"));
+        assert!(matches!(ret, Err(Error::NotFound(_))));
+
+        let ret = signed.diff_path(&product, DVHashType::Sha256, Path::new("/etc"));
+        assert!(matches!(ret, Err(Error::InvalidArgument(_))));
+    }
+
+    #[test]
+    fn walker_jobs() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let product = tmp_dir.path().join("product");
+        write_test_tree(&product);
+
+        for i in 0..64 {
+            let sub_dir = product
+                .join(format!("dir{}", i % 8))
+                .join(format!("sub{}", i % 3));
+            fs::create_dir_all(&sub_dir).unwrap();
+            fs::write(sub_dir.join(format!("file{i}")), vec![i as u8; i * 1024]).unwrap();
+        }
+
+        let mut encoded = vec![];
+
+        for jobs in [1, 2, 8, 0] {
+            let mut walker = Walker::with_header(WalkerHeader::default());
+            walker.jobs = jobs;
+            walker.walk(&product, DVHashType::Sha256).unwrap();
+            encoded.push(walker.encode().unwrap());
+        }
+
+        assert!(encoded.windows(2).all(|w| w[0] == w[1]));
+    }
 }
@@ -0,0 +1,194 @@
+use std::{
+    io,
+    path::Path,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use log::warn;
+use serde_derive::{Deserialize, Serialize};
+
+use crate::{
+    common::serializer::{hex_deserializer, hex_serializer},
+    error::Error,
+};
+
+const XATTR_TAG: u8 = b'x';
+
+// a filesystem without xattrs is only reported once per run
+static UNSUPPORTED_WARNED: AtomicBool = AtomicBool::new(false);
+
+//
+// an extended attribute as read from the file, SELinux labels are
+// security.selinux and file capabilities security.capability
+//
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WalkerXattr {
+    pub name: String,
+    #[serde(
+        serialize_with = "hex_serializer",
+        deserialize_with = "hex_deserializer"
+    )]
+    pub value: Vec<u8>,
+}
+
+//
+// "security" selects the whole namespace, "security.selinux" only the label
+//
+pub fn is_selected(name: &str, namespaces: &[String]) -> bool {
+    namespaces.iter().any(|ns| {
+        name == ns
+            || name
+                .strip_prefix(ns.as_str())
+                .is_some_and(|rest| rest.starts_with('.') || ns.ends_with('.'))
+    })
+}
+
+fn is_unsupported(e: &io::Error) -> bool {
+    e.kind() == io::ErrorKind::Unsupported || e.raw_os_error() == Some(libc::ENOTSUP)
+}
+
+////////////////////////////////////////////////////////////////////////////////
+/// PUBLIC
+////////////////////////////////////////////////////////////////////////////////
+//
+// the xattrs of the given namespaces sorted by name. A filesystem that doesn't
+// support them has none
+//
+pub fn read_xattrs<P: AsRef<Path>>(
+    path: P,
+    namespaces: &[String],
+) -> Result<Vec<WalkerXattr>, Error> {
+    if namespaces.is_empty() {
+        return Ok(vec![]);
+    }
+
+    let path = path.as_ref();
+
+    let names = match xattr::list_deref(path) {
+        Ok(v) => v,
+        Err(e) if is_unsupported(&e) => {
+            if !UNSUPPORTED_WARNED.swap(true, Ordering::Relaxed) {
+                warn!("{:?} doesn't support extended attributes", path);
+            }
+            return Ok(vec![]);
+        }
+        Err(e) => return Err(e.into()),
+    };
+
+    let mut xattrs = Vec::new();
+
+    for name in names {
+        let name = match name.into_string() {
+            Ok(v) => v,
+            Err(name) => {
+                warn!("{:?} has a non utf-8 extended attribute {:?}", path, name);
+                continue;
+            }
+        };
+
+        if !is_selected(&name, namespaces) {
+            continue;
+        }
+
+        // removed since it was listed
+        if let Some(value) = xattr::get_deref(path, &name)? {
+            xattrs.push(WalkerXattr { name, value });
+        }
+    }
+
+    xattrs.sort_by(|a, b| a.name.cmp(&b.name));
+
+    Ok(xattrs)
+}
+
+//
+// the xattrs of the given namespaces out of all those recorded elsewhere, in an
+// archive
+//
+pub fn select_xattrs(xattrs: &[WalkerXattr], namespaces: &[String]) -> Vec<WalkerXattr> {
+    xattrs
+        .iter()
+        .filter(|x| is_selected(&x.name, namespaces))
+        .cloned()
+        .collect()
+}
+
+pub fn encode_xattrs(xattrs: &[WalkerXattr]) -> Vec<u8> {
+    let mut out = Vec::new();
+
+    for xattr in xattrs {
+        out.push(XATTR_TAG);
+        out.extend((xattr.name.len() as u32).to_be_bytes());
+        out.extend(xattr.name.as_bytes());
+        out.extend((xattr.value.len() as u32).to_be_bytes());
+        out.extend(&xattr.value);
+    }
+
+    out
+}
+
+//
+// names of the xattrs that were added, removed or changed
+//
+pub fn diff_xattrs(signed: &[WalkerXattr], current: &[WalkerXattr]) -> Vec<String> {
+    let mut names: Vec<&str> = signed
+        .iter()
+        .chain(current)
+        .map(|x| x.name.as_str())
+        .collect();
+    names.sort();
+    names.dedup();
+
+    let value = |xattrs: &[WalkerXattr], name: &str| {
+        xattrs
+            .iter()
+            .find(|x| x.name == name)
+            .map(|x| x.value.clone())
+    };
+
+    names
+        .into_iter()
+        .filter(|name| value(signed, name) != value(current, name))
+        .map(String::from)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::fs;
+
+    use super::*;
+
+    #[test]
+    fn walker_xattrs() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let file = tmp_dir.path().join("file");
+        fs::write(&file, "hello").unwrap();
+
+        let namespaces = vec!["user.dver".to_string()];
+
+        xattr::set(&file, "user.dver", b"label").unwrap();
+        xattr::set(&file, "user.dver.cap", b"cap").unwrap();
+        xattr::set(&file, "user.dverx", b"other").unwrap();
+
+        let xattrs = read_xattrs(&file, &namespaces).unwrap();
+        let names: Vec<&str> = xattrs.iter().map(|x| x.name.as_str()).collect();
+        assert_eq!(names, ["user.dver", "user.dver.cap"]);
+        assert_eq!(xattrs[0].value, b"label");
+
+        assert!(read_xattrs(&file, &[]).unwrap().is_empty());
+        assert_eq!(read_xattrs(&file, &["user.".into()]).unwrap().len(), 3);
+
+        xattr::set(&file, "user.dver", b"tampered").unwrap();
+        xattr::remove(&file, "user.dver.cap").unwrap();
+
+        let current = read_xattrs(&file, &namespaces).unwrap();
+        assert_eq!(
+            diff_xattrs(&xattrs, &current),
+            ["user.dver", "user.dver.cap"]
+        );
+        assert_ne!(encode_xattrs(&xattrs), encode_xattrs(&current));
+        assert!(encode_xattrs(&[]).is_empty());
+    }
+}
@@ -9,6 +9,8 @@ use std::{
 use derive_more::From;
 use pem::PemError;
 
+use crate::common::hash::DVHashType;
+
 pub type Result<T> = core::result::Result<T, Error>;
 
 #[derive(Debug, From)]
@@ -16,11 +18,18 @@ pub enum Error {
     NotFound(String),
     InvalidArgument(String),
     SerializeError,
-    InputKeyFormatNotSupported,
+    InputKeyFormatNotSupported(String),
     NotImplementedError(String),
     InvalidRootDirectory,
     UnknownHashType,
+    HashTypeMismatch {
+        signed: DVHashType,
+        expected: DVHashType,
+    },
     UnknownSignatureType,
+    UnknownSignatureFormat,
+    UnknownOutputFormat,
+    UnsupportedFormatVersion(u8),
     LoggingInitFailure,
     InvalidPath(String),
     EmptyHash,
@@ -35,9 +44,23 @@ pub enum Error {
     #[from]
     LoadKey(ssh_key::Error),
     #[from]
+    Rsa(rsa::Error),
+    #[from]
+    Pkcs8(pkcs8::Error),
+    #[from]
+    Der(pkcs8::der::Error),
+    #[from]
+    Spki(pkcs8::spki::Error),
+    #[from]
     SshAgentUnknownMessage(String),
     #[from]
     Pem(PemError),
+    #[from]
+    Exclude(ignore::Error),
+    #[from]
+    ThreadPool(rayon::ThreadPoolBuildError),
+    #[from]
+    Zip(zip::result::ZipError),
 
     //
     // String
@@ -53,8 +76,8 @@ pub enum Error {
     #[from]
     NotInPath(which::Error),
     ExecFailure {
-        command: Command,
-        output: Output,
+        command: Box<Command>,
+        output: Box<Output>,
     },
 
     //
@@ -63,9 +86,16 @@ pub enum Error {
     KeyInvalidType,
 
     //
-    //
+    // Verification
     //
     VerificationFailure,
+    // a signature file or signed manifest that doesn't parse
+    InvalidSignature(String),
+    SignatureExpired,
+    SignatureNotYetValid,
+    DirectoryModified,
+    PermissionsModified,
+    FileModified,
 
     //
     // Base64
@@ -86,3 +116,33 @@ impl core::fmt::Display for Error {
 }
 
 impl std::error::Error for Error {}
+
+pub const EXIT_ERROR: i32 = 1;
+pub const EXIT_SIGNATURE_INVALID: i32 = 2;
+pub const EXIT_DIRECTORY_MODIFIED: i32 = 3;
+pub const EXIT_PERMISSIONS_MODIFIED: i32 = 4;
+
+impl Error {
+    //
+    // what can't be read from a signature makes it invalid, unless it's only
+    // too recent for us
+    //
+    pub fn invalid_signature(self) -> Error {
+        match self {
+            Error::Io(_) | Error::UnsupportedFormatVersion(_) | Error::InvalidSignature(_) => self,
+            e => Error::InvalidSignature(e.to_string()),
+        }
+    }
+
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Error::VerificationFailure
+            | Error::InvalidSignature(_)
+            | Error::SignatureExpired
+            | Error::SignatureNotYetValid => EXIT_SIGNATURE_INVALID,
+            Error::DirectoryModified | Error::FileModified => EXIT_DIRECTORY_MODIFIED,
+            Error::PermissionsModified => EXIT_PERMISSIONS_MODIFIED,
+            _ => EXIT_ERROR,
+        }
+    }
+}
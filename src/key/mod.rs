@@ -1,3 +1,4 @@
+pub mod key_type;
 pub mod keys;
 mod pgp;
 mod ssh;
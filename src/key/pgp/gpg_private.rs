@@ -13,16 +13,22 @@ use which::which;
 
 use crate::{
     error::{Error, Result},
-    key::{self, keys::Signer, pgp::pgp_common::log_command_failure},
+    key::{
+        self,
+        keys::Signer,
+        pgp::pgp_common::{log_command_failure, GpgHome},
+    },
 };
 
 #[derive(Debug)]
 pub struct GpgPrivate {
     key_id: Option<String>,
+    home: Option<GpgHome>,
 }
 
 fn run_pgp(
     gpg_exe: &Path,
+    home: Option<&Path>,
     key_id: &Option<String>,
     ask_pass: bool,
     in_file: &Path,
@@ -30,6 +36,10 @@ fn run_pgp(
 ) -> Result<()> {
     let mut command = Command::new(gpg_exe);
 
+    if let Some(home) = home {
+        command.arg("--homedir").arg(home);
+    }
+
     if let Some(key) = &key_id {
         command.arg("--default-key");
         command.arg(key);
@@ -60,12 +70,10 @@ fn run_pgp(
 
     let mut child = command.stdin(Stdio::piped()).spawn()?;
 
-    if ask_pass {
-        if let Some(mut stdin) = child.stdin.take() {
-            let password = rpassword::prompt_password("gpg passphrase: ")?;
-            stdin.write_all(password.as_bytes())?;
-            info!("password sent");
-        }
+    if ask_pass && let Some(mut stdin) = child.stdin.take() {
+        let password = rpassword::prompt_password("gpg passphrase: ")?;
+        stdin.write_all(password.as_bytes())?;
+        info!("password sent");
     }
 
     let output = child.wait_with_output()?;
@@ -77,13 +85,16 @@ fn run_pgp(
         _ => {
             log_command_failure(&output);
             let msg = format!("{:?} returned {exit_code}", gpg_exe.display());
-            Err(Error::ExecFailure { command, output })
+            Err(Error::ExecFailure {
+                command: Box::new(command),
+                output: Box::new(output),
+            })
         }
     }
 }
 
 impl Signer for GpgPrivate {
-    fn sign(&mut self, data: &[u8]) -> Result<Vec<u8>> {
+    fn sign(&mut self, _namespace: &str, data: &[u8]) -> Result<Vec<u8>> {
         let tmp_dir = Builder::new().prefix("dver_gpg_").tempdir()?;
 
         let in_file = Path::new(tmp_dir.path()).join("input.bin");
@@ -96,8 +107,10 @@ impl Signer for GpgPrivate {
         //
         // trying without a password first in case the agent is running
         //
-        if run_pgp(&gpg_exe, &self.key_id, false, &in_file, &out_file).is_err() {
-            run_pgp(&gpg_exe, &self.key_id, true, &in_file, &out_file)?;
+        let home = self.home.as_ref().map(|h| h.path());
+
+        if run_pgp(&gpg_exe, home, &self.key_id, false, &in_file, &out_file).is_err() {
+            run_pgp(&gpg_exe, home, &self.key_id, true, &in_file, &out_file)?;
         }
 
         let sig_data = fs::read(&out_file)?;
@@ -106,16 +119,40 @@ impl Signer for GpgPrivate {
 
         Ok(sig_data)
     }
+
+    fn fingerprint(&self) -> Option<String> {
+        self.key_id.clone()
+    }
 }
 
 impl GpgPrivate {
     pub fn new_with_key(private_key_id: &str) -> GpgPrivate {
         GpgPrivate {
             key_id: Some(private_key_id.to_string()),
+            home: None,
         }
     }
 
     pub fn new() -> GpgPrivate {
-        GpgPrivate { key_id: None }
+        GpgPrivate {
+            key_id: None,
+            home: None,
+        }
+    }
+
+    pub fn new_with_key_file<P: AsRef<Path>>(key_file: P) -> Result<GpgPrivate> {
+        let gpg_exe = which("gpg")?;
+
+        let home = GpgHome::import(&gpg_exe, key_file)?;
+        let key_id = home.fingerprint(&gpg_exe, true)?;
+
+        if key_id.is_none() {
+            return Err(Error::NotFound("no secret key in the pgp key file".into()));
+        }
+
+        Ok(GpgPrivate {
+            key_id,
+            home: Some(home),
+        })
     }
 }
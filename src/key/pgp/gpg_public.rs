@@ -3,22 +3,95 @@ use std::{fs, path::Path, process::Command};
 use tempfile::Builder;
 use which::which;
 
-use log::info;
+use log::{error, info};
 
 use crate::{
     error::{Error, Result},
-    key::{keys::Verifier, pgp::pgp_common::log_command_failure},
+    key::{
+        keys::Verifier,
+        pgp::pgp_common::{log_command_failure, GpgHome},
+    },
 };
 
 pub struct GpgPublic {
     key_id: Option<String>,
+    home: Option<GpgHome>,
 }
 
-fn gpg_verify(gpg_exe: &Path, key_id: &Option<String>, msg: &Path, sig: &Path) -> Result<()> {
+//
+// the primary key fingerprint of the one key key_id names, gpg:// keys are
+// pinned to it
+//
+fn primary_fingerprint(gpg_exe: &Path, home: Option<&Path>, key_id: &str) -> Result<String> {
     let mut command = Command::new(gpg_exe);
 
-    if let Some(key) = &key_id {
-        command.arg("--default-key").arg(key);
+    if let Some(home) = home {
+        command.arg("--homedir").arg(home);
+    }
+
+    command
+        .arg("--batch")
+        .arg("--with-colons")
+        .arg("--list-keys")
+        .arg("--")
+        .arg(key_id);
+
+    info!("-----------------------------------");
+    info!("command: {:?}", command);
+
+    let output = command.output()?;
+    let listing = String::from_utf8(output.stdout)?;
+
+    // the fpr line following a pub line is the primary key's
+    let fingerprints: Vec<&str> = listing
+        .lines()
+        .zip(listing.lines().skip(1))
+        .filter(|(key, _)| key.starts_with("pub:"))
+        .filter_map(|(_, fpr)| fpr.strip_prefix("fpr:"))
+        .filter_map(|fpr| fpr.split(':').find(|f| !f.is_empty()))
+        .collect();
+
+    match fingerprints[..] {
+        [fingerprint] => Ok(fingerprint.to_string()),
+        [] => Err(Error::NotFound(format!("no gpg key {key_id}"))),
+        _ => Err(Error::InvalidArgument(format!(
+            "{} gpg keys match {key_id}",
+            fingerprints.len()
+        ))),
+    }
+}
+
+//
+// [GNUPG:] VALIDSIG <fpr> <date> <time> <expires> <version> <reserved> <pk algo>
+// <hash algo> <class> <primary fpr>
+//
+fn valid_signers(status: &str) -> Vec<&str> {
+    status
+        .lines()
+        .filter_map(|l| l.strip_prefix("[GNUPG:] VALIDSIG "))
+        .flat_map(|l| {
+            let fields: Vec<&str> = l.split(' ').collect();
+            [fields.first().copied(), fields.get(9).copied()]
+        })
+        .flatten()
+        .collect()
+}
+
+//
+// gpg --verify takes any key in the keyring, whoever made the signature has to
+// be checked against the key it's pinned to
+//
+fn gpg_verify(
+    gpg_exe: &Path,
+    home: Option<&Path>,
+    key_id: &Option<String>,
+    msg: &Path,
+    sig: &Path,
+) -> Result<()> {
+    let mut command = Command::new(gpg_exe);
+
+    if let Some(home) = home {
+        command.arg("--homedir").arg(home);
     }
 
     command
@@ -26,6 +99,8 @@ fn gpg_verify(gpg_exe: &Path, key_id: &Option<String>, msg: &Path, sig: &Path) -
         .arg("--pinentry-mode")
         .arg("loopback")
         .arg("--no-tty")
+        .arg("--status-fd")
+        .arg("1")
         .arg("--verify")
         .arg(sig.to_str().unwrap_or(""))
         .arg(msg.to_str().unwrap_or(""));
@@ -37,17 +112,36 @@ fn gpg_verify(gpg_exe: &Path, key_id: &Option<String>, msg: &Path, sig: &Path) -
 
     let exit_code = output.status.code().unwrap_or(1);
 
-    match exit_code {
-        0 => Ok(()),
-        _ => {
-            log_command_failure(&output);
-            Err(Error::ExecFailure { command, output })
+    // 1 is a bad signature, 2 one gpg can't check, neither is valid
+    if exit_code != 0 {
+        log_command_failure(&output);
+        return Err(Error::VerificationFailure);
+    }
+
+    let status = String::from_utf8_lossy(&output.stdout);
+    let signers = valid_signers(&status);
+
+    info!("signed by {signers:?}");
+
+    let pinned = match key_id {
+        Some(key) => signers.iter().any(|s| s.eq_ignore_ascii_case(key)),
+        None => !signers.is_empty(),
+    };
+
+    match pinned {
+        true => Ok(()),
+        false => {
+            error!(
+                "the signature isn't from {}",
+                key_id.as_deref().unwrap_or("gpg")
+            );
+            Err(Error::VerificationFailure)
         }
     }
 }
 
 impl Verifier for GpgPublic {
-    fn verify(&self, msg: &[u8], signature: &[u8]) -> Result<()> {
+    fn verify(&self, _namespace: &str, msg: &[u8], signature: &[u8]) -> Result<()> {
         let tmp_dir = Builder::new().prefix("dver_gpg_").tempdir()?;
 
         let msg_file = Path::new(tmp_dir.path()).join("msg.bin");
@@ -58,18 +152,54 @@ impl Verifier for GpgPublic {
 
         let gpg_exe = which("gpg")?;
 
-        gpg_verify(&gpg_exe, &self.key_id, &msg_file, &sig_file)
+        let home = self.home.as_ref().map(|h| h.path());
+
+        gpg_verify(&gpg_exe, home, &self.key_id, &msg_file, &sig_file)
+    }
+
+    fn fingerprint(&self) -> Option<String> {
+        self.key_id.clone()
     }
 }
 
 impl GpgPublic {
-    pub fn new_with_key(private_key_id: &str) -> GpgPublic {
+    pub fn new_with_key(key_id: &str) -> Result<GpgPublic> {
+        GpgPublic::new_in_home(None, key_id)
+    }
+
+    //
+    // key_id in the given keyring, the user's one without
+    //
+    pub fn new_in_home(home: Option<GpgHome>, key_id: &str) -> Result<GpgPublic> {
+        let gpg_exe = which("gpg")?;
+
+        let path = home.as_ref().map(|h| h.path());
+        let fingerprint = primary_fingerprint(&gpg_exe, path, key_id)?;
+
+        info!("gpg key {key_id} is {fingerprint}");
+
+        Ok(GpgPublic {
+            key_id: Some(fingerprint),
+            home,
+        })
+    }
+
+    pub fn new() -> GpgPublic {
         GpgPublic {
-            key_id: Some(private_key_id.to_string()),
+            key_id: None,
+            home: None,
         }
     }
 
-    pub fn new() -> GpgPublic {
-        GpgPublic { key_id: None }
+    pub fn new_with_key_file<P: AsRef<Path>>(key_file: P) -> Result<GpgPublic> {
+        let gpg_exe = which("gpg")?;
+
+        let home = GpgHome::import(&gpg_exe, key_file)?;
+        let key_id = home.fingerprint(&gpg_exe, false)?;
+
+        Ok(GpgPublic {
+            key_id,
+            home: Some(home),
+        })
     }
 }
@@ -1,4 +1,3 @@
 pub mod gpg_private;
 pub mod gpg_public;
-
-mod pgp_common;
+pub mod pgp_common;
@@ -1,6 +1,12 @@
-use std::process::Output;
+use std::{
+    path::Path,
+    process::{Command, Output, Stdio},
+};
 
-use log::error;
+use log::{error, info};
+use tempfile::{Builder, TempDir};
+
+use crate::error::{Error, Result};
 
 pub fn log_command_failure(output: &Output) {
     if !output.stdout.is_empty() {
@@ -11,3 +17,89 @@ pub fn log_command_failure(output: &Output) {
         error!("{}", String::from_utf8_lossy(&output.stderr))
     }
 }
+
+//
+// keys given as files are imported in a throw away keyring so the user's own
+// keyring is left alone
+//
+#[derive(Debug)]
+pub struct GpgHome {
+    dir: TempDir,
+}
+
+impl GpgHome {
+    pub fn import<P: AsRef<Path>>(gpg_exe: &Path, key_file: P) -> Result<GpgHome> {
+        let dir = Builder::new().prefix("dver_gpg_home_").tempdir()?;
+
+        let mut command = Command::new(gpg_exe);
+
+        command
+            .arg("--homedir")
+            .arg(dir.path())
+            .arg("--batch")
+            .arg("--no-tty")
+            .arg("--import")
+            .arg(key_file.as_ref());
+
+        info!("-----------------------------------");
+        info!("command: {:?}", command);
+
+        let output = command.output()?;
+
+        if !output.status.success() {
+            log_command_failure(&output);
+            return Err(Error::ExecFailure {
+                command: Box::new(command),
+                output: Box::new(output),
+            });
+        }
+
+        Ok(GpgHome { dir })
+    }
+
+    pub fn path(&self) -> &Path {
+        self.dir.path()
+    }
+
+    //
+    // fingerprint of the first key found in the keyring
+    //
+    pub fn fingerprint(&self, gpg_exe: &Path, secret: bool) -> Result<Option<String>> {
+        let list = match secret {
+            true => "--list-secret-keys",
+            false => "--list-keys",
+        };
+
+        let output = Command::new(gpg_exe)
+            .arg("--homedir")
+            .arg(self.path())
+            .arg("--batch")
+            .arg("--with-colons")
+            .arg(list)
+            .output()?;
+
+        let listing = String::from_utf8(output.stdout)?;
+
+        let fingerprint = listing
+            .lines()
+            .find_map(|l| l.strip_prefix("fpr:"))
+            .and_then(|l| l.split(':').find(|f| !f.is_empty()))
+            .map(String::from);
+
+        Ok(fingerprint)
+    }
+}
+
+impl Drop for GpgHome {
+    fn drop(&mut self) {
+        // importing a secret key starts an agent bound to the home directory
+        let _ = Command::new("gpgconf")
+            .arg("--homedir")
+            .arg(self.path())
+            .arg("--kill")
+            .arg("all")
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status();
+    }
+}
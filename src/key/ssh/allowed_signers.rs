@@ -0,0 +1,292 @@
+use std::{fs, path::Path};
+
+use log::warn;
+use ssh_key::PublicKey;
+use time::{Date, Month, PrimitiveDateTime, Time};
+
+use crate::error::{Error, Result};
+
+//
+// OpenSSH allowed_signers, see ssh-keygen(1)
+//
+// principals [options] keytype base64-key [comment]
+//
+#[derive(Debug)]
+pub struct AllowedSigner {
+    pub principals: String,
+    pub namespaces: Option<String>,
+    pub valid_after: Option<u64>,
+    pub valid_before: Option<u64>,
+    pub key: PublicKey,
+}
+
+//
+// '*' and '?' wildcards, same as OpenSSH match_pattern()
+//
+fn match_pattern(pattern: &[u8], s: &[u8]) -> bool {
+    match (pattern.first(), s.first()) {
+        (None, None) => true,
+        (Some(b'*'), _) => {
+            match_pattern(&pattern[1..], s) || (!s.is_empty() && match_pattern(pattern, &s[1..]))
+        }
+        (Some(b'?'), Some(_)) => match_pattern(&pattern[1..], &s[1..]),
+        (Some(p), Some(c)) if p == c => match_pattern(&pattern[1..], &s[1..]),
+        _ => false,
+    }
+}
+
+//
+// comma separated patterns, a match on a negated pattern wins
+//
+pub fn match_pattern_list(patterns: &str, s: &str) -> bool {
+    let mut found = false;
+
+    for pattern in patterns.split(',') {
+        let (negated, pattern) = match pattern.strip_prefix('!') {
+            Some(v) => (true, v),
+            None => (false, pattern),
+        };
+
+        if match_pattern(pattern.as_bytes(), s.as_bytes()) {
+            if negated {
+                return false;
+            }
+            found = true;
+        }
+    }
+
+    found
+}
+
+//
+// YYYYMMDD[Z] or YYYYMMDDHHMM[SS][Z]. Times without the Z suffix are local
+// time for OpenSSH, we treat everything as UTC
+//
+fn parse_timestamp(value: &str) -> Result<u64> {
+    let invalid = || Error::InvalidArgument(format!("invalid timestamp {value:?}"));
+
+    let digits = value.strip_suffix(['Z', 'z']).unwrap_or(value);
+
+    if !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(invalid());
+    }
+
+    let num = |start: usize, len: usize| -> Result<u32> {
+        match digits.get(start..start + len) {
+            Some(v) => v.parse().map_err(|_| invalid()),
+            None => Ok(0),
+        }
+    };
+
+    if ![8, 12, 14].contains(&digits.len()) {
+        return Err(invalid());
+    }
+
+    let month = Month::try_from(num(4, 2)? as u8).map_err(|_| invalid())?;
+    let date = Date::from_calendar_date(num(0, 4)? as i32, month, num(6, 2)? as u8);
+    let time = Time::from_hms(num(8, 2)? as u8, num(10, 2)? as u8, num(12, 2)? as u8);
+
+    match (date, time) {
+        (Ok(date), Ok(time)) => {
+            let ts = PrimitiveDateTime::new(date, time)
+                .assume_utc()
+                .unix_timestamp();
+            u64::try_from(ts).map_err(|_| invalid())
+        }
+        _ => Err(invalid()),
+    }
+}
+
+//
+// splits the first field, honoring double quotes
+//
+fn next_field(line: &str) -> (&str, &str) {
+    let mut quoted = false;
+
+    for (i, c) in line.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            ' ' | '\t' if !quoted => return (&line[..i], line[i..].trim_start()),
+            _ => {}
+        }
+    }
+
+    (line, "")
+}
+
+fn split_options(options: &str) -> Vec<&str> {
+    let mut quoted = false;
+    let mut start = 0;
+    let mut list = Vec::new();
+
+    for (i, c) in options.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            ',' if !quoted => {
+                list.push(&options[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+
+    list.push(&options[start..]);
+    list
+}
+
+impl AllowedSigner {
+    fn parse_options(&mut self, options: &str) -> Result<()> {
+        for option in split_options(options) {
+            let (name, value) = match option.split_once('=') {
+                Some((n, v)) => (n, Some(v.trim_matches('"'))),
+                None => (option, None),
+            };
+
+            match (name.to_lowercase().as_str(), value) {
+                ("namespaces", Some(v)) => self.namespaces = Some(v.into()),
+                ("valid-after", Some(v)) => self.valid_after = Some(parse_timestamp(v)?),
+                ("valid-before", Some(v)) => self.valid_before = Some(parse_timestamp(v)?),
+                _ => {
+                    let msg = format!("unsupported allowed signers option {option:?}");
+                    return Err(Error::NotImplementedError(msg));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn parse(line: &str) -> Result<AllowedSigner> {
+        let (principals, rest) = next_field(line.trim());
+
+        if rest.is_empty() {
+            return Err(Error::InvalidArgument(format!("invalid signer {line:?}")));
+        }
+
+        let principals = principals.trim_matches('"').to_string();
+
+        //
+        // options are optional, if what follows the principals isn't a key
+        // it has to be the options
+        //
+        let (options, key) = match PublicKey::from_openssh(rest) {
+            Ok(key) => (None, key),
+            Err(_) => {
+                let (options, key) = next_field(rest);
+                (Some(options), PublicKey::from_openssh(key)?)
+            }
+        };
+
+        let mut signer = AllowedSigner {
+            principals,
+            namespaces: None,
+            valid_after: None,
+            valid_before: None,
+            key,
+        };
+
+        if let Some(options) = options {
+            signer.parse_options(options)?;
+        }
+
+        Ok(signer)
+    }
+
+    //
+    // at is the verification time, like ssh-keygen -Y verify with
+    // -Overify-time
+    //
+    pub fn allows(&self, namespace: &str, at: u64) -> bool {
+        if let Some(namespaces) = &self.namespaces
+            && !match_pattern_list(namespaces, namespace)
+        {
+            return false;
+        }
+
+        if let Some(valid_after) = self.valid_after
+            && at < valid_after
+        {
+            return false;
+        }
+
+        if let Some(valid_before) = self.valid_before
+            && at > valid_before
+        {
+            return false;
+        }
+
+        true
+    }
+}
+
+pub fn load_allowed_signers<P: AsRef<Path>>(path: P) -> Result<Vec<AllowedSigner>> {
+    let data = fs::read_to_string(path)?;
+
+    let mut signers = Vec::new();
+
+    for (i, line) in data.lines().enumerate() {
+        let line = line.trim();
+
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        match AllowedSigner::parse(line) {
+            Ok(v) => signers.push(v),
+            Err(e) => warn!("allowed signers line {}: {e}", i + 1),
+        }
+    }
+
+    Ok(signers)
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    const KEY: &str = "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIFDf8usjlOlyiOYjzYWL3xDamT9QigjOGctdGZ2Qo/Vy joe@laptop";
+
+    #[test]
+    fn patterns() {
+        assert!(match_pattern_list("dver@*", "dver@uintptr.github.io"));
+        assert!(match_pattern_list("git,dver@*", "dver@example.com"));
+        assert!(!match_pattern_list("git,file", "dver@example.com"));
+        assert!(!match_pattern_list("*,!dver@*", "dver@example.com"));
+        assert!(match_pattern_list("h?llo", "hello"));
+    }
+
+    #[test]
+    fn timestamps() {
+        assert_eq!(parse_timestamp("19700102").unwrap(), 86400);
+        assert_eq!(parse_timestamp("19700101000001Z").unwrap(), 1);
+        assert_eq!(parse_timestamp("197001010001").unwrap(), 60);
+        assert!(parse_timestamp("1970").is_err());
+        assert!(parse_timestamp("19701301").is_err());
+    }
+
+    #[test]
+    fn parse_signers() {
+        let signer = AllowedSigner::parse(&format!("joe@example.com {KEY}")).unwrap();
+        assert_eq!(signer.principals, "joe@example.com");
+        assert!(signer.allows("anything", 0));
+
+        let line = format!(
+            "\"joe,ann\" namespaces=\"dver@*,file\",\
+             valid-after=\"20250101\",valid-before=20260101 {KEY}"
+        );
+        let signer = AllowedSigner::parse(&line).unwrap();
+        assert_eq!(signer.principals, "joe,ann");
+
+        let inside = parse_timestamp("20250601").unwrap();
+        let after = parse_timestamp("20260601").unwrap();
+
+        assert!(signer.allows("dver@uintptr.github.io", inside));
+        assert!(!signer.allows("git", inside));
+        assert!(!signer.allows("dver@uintptr.github.io", after));
+        assert!(!signer.allows("dver@uintptr.github.io", 0));
+
+        let line = format!("joe cert-authority {KEY}");
+        assert!(AllowedSigner::parse(&line).is_err());
+    }
+}
@@ -1,4 +1,7 @@
+pub mod allowed_signers;
+pub mod pkcs8;
 pub mod ssh_private;
 pub mod ssh_public;
 
 mod ssh_agent;
+mod ssh_common;
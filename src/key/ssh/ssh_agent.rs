@@ -1,18 +1,19 @@
 use std::{
-    env, fs,
+    env,
     io::{Read, Write},
     os::unix::net::UnixStream,
     path::Path,
     vec,
 };
 
-pub const DV_NS: &[u8] = b"hello";
-pub const DV_NS_STR: &str = "hello";
 const SIG_ALG: &[u8] = b"sha512";
 
+// ask the agent for rsa-sha2-512 rather than the legacy sha1 ssh-rsa
+const SSH_AGENT_RSA_SHA2_512: u32 = 0x04;
+
 use log::info;
 use serde_derive::Serialize;
-use ssh_key::PrivateKey;
+use ssh_key::{PublicKey, Signature};
 
 use crate::{
     common::hash::{hash_data, DVHashType},
@@ -56,10 +57,40 @@ pub struct SshAgentClient {
     stream: UnixStream,
 }
 
+//
+// key_blob is the key in the ssh wire format, it starts with the algorithm
+//
 #[derive(Debug)]
 pub struct SshIdentity {
     algorithm: String,
-    pub_key: Vec<u8>,
+    key_blob: Vec<u8>,
+}
+
+impl SshIdentity {
+    fn new(key_blob: Vec<u8>) -> Result<SshIdentity> {
+        let algorithm = match key_blob.get(..4) {
+            Some(v) => {
+                let len = u32::from_be_bytes(v.try_into().expect("slice length")) as usize;
+                match key_blob.get(4..4 + len) {
+                    Some(v) => std::str::from_utf8(v)?.to_string(),
+                    None => return Err(Error::SshAgentUnknownMessage("key blob".into())),
+                }
+            }
+            None => return Err(Error::SshAgentUnknownMessage("key blob".into())),
+        };
+
+        Ok(SshIdentity {
+            algorithm,
+            key_blob,
+        })
+    }
+
+    fn sign_flags(&self) -> u32 {
+        match self.algorithm.as_str() {
+            "ssh-rsa" => SSH_AGENT_RSA_SHA2_512,
+            _ => 0,
+        }
+    }
 }
 
 impl SshAgentClient {
@@ -73,6 +104,10 @@ impl SshAgentClient {
             }
         };
 
+        SshAgentClient::new_with_socket(auth_sock)
+    }
+
+    pub fn new_with_socket<P: AsRef<Path>>(auth_sock: P) -> Result<SshAgentClient> {
         let stream = UnixStream::connect(auth_sock)?;
 
         Ok(SshAgentClient { stream })
@@ -90,18 +125,6 @@ impl SshAgentClient {
         Ok(u8::from_be_bytes(buffer))
     }
 
-    fn read_string(&mut self) -> Result<String> {
-        let len = self.read_u32()?;
-
-        let mut str_data = vec![0u8; len as usize];
-
-        self.stream.read_exact(&mut str_data)?;
-
-        let str = std::str::from_utf8(&str_data)?;
-
-        Ok(str.into())
-    }
-
     fn read_buffer(&mut self) -> Result<Vec<u8>> {
         let len = self.read_u32()?;
 
@@ -113,13 +136,10 @@ impl SshAgentClient {
     }
 
     fn read_identity(&mut self) -> Result<SshIdentity> {
-        self.read_u32()?;
+        let key_blob = self.read_buffer()?;
+        self.read_buffer()?; // comment
 
-        let algorithm = self.read_string()?;
-        let pub_key = self.read_buffer()?;
-        self.read_string()?; // comment
-
-        Ok(SshIdentity { algorithm, pub_key })
+        SshIdentity::new(key_blob)
     }
 
     fn read_list_keys_answer(&mut self) -> Result<Vec<SshIdentity>> {
@@ -138,22 +158,21 @@ impl SshAgentClient {
         Ok(identities)
     }
 
-    fn send_signing_request(&mut self, identity: &SshIdentity, data: &[u8]) -> Result<()> {
+    fn send_signing_request(
+        &mut self,
+        identity: &SshIdentity,
+        namespace: &str,
+        data: &[u8],
+    ) -> Result<()> {
         // public key
-        let mut pub_key_vec: Vec<u8> = Vec::new();
-        let alg_len = identity.algorithm.len() as u32;
-        pub_key_vec.extend(alg_len.to_be_bytes());
-        pub_key_vec.extend(identity.algorithm.as_bytes());
-        let key_len = identity.pub_key.len() as u32;
-        pub_key_vec.extend(key_len.to_be_bytes());
-        pub_key_vec.extend(&identity.pub_key);
+        let pub_key_vec = &identity.key_blob;
 
         // sshsig
         let mut ssh_sig_vec: Vec<u8> = Vec::new();
         ssh_sig_vec.extend(b"SSHSIG");
-        let ns_len = DV_NS.len() as u32;
+        let ns_len = namespace.len() as u32;
         ssh_sig_vec.extend(ns_len.to_be_bytes());
-        ssh_sig_vec.extend(DV_NS);
+        ssh_sig_vec.extend(namespace.as_bytes());
         let ns_res_string: u32 = 0;
         ssh_sig_vec.extend(ns_res_string.to_be_bytes());
         let sig_alg_len = SIG_ALG.len() as u32;
@@ -167,7 +186,7 @@ impl SshAgentClient {
         let msg_len = 4 + 1 + pub_key_vec.len() + ssh_sig_vec.len() + 8;
         let msg_len = msg_len as u32;
         let sign_msg_id: u8 = 0xd;
-        let zero: u32 = 0;
+        let flags = identity.sign_flags();
 
         let mut sign_msg: Vec<u8> = Vec::new();
 
@@ -179,7 +198,7 @@ impl SshAgentClient {
         let ssh_sig_len = ssh_sig_vec.len() as u32;
         sign_msg.extend(ssh_sig_len.to_be_bytes());
         sign_msg.extend(ssh_sig_vec);
-        sign_msg.extend(zero.to_be_bytes());
+        sign_msg.extend(flags.to_be_bytes());
 
         self.stream.write_all(&sign_msg)?;
 
@@ -189,17 +208,12 @@ impl SshAgentClient {
     ////////////////////////////////////////////////////////////////////////////
     /// PUBLIC
     ////////////////////////////////////////////////////////////////////////////
-    pub fn find_identity<P: AsRef<Path>>(&mut self, key_file: P) -> Result<SshIdentity> {
-        let key_data = fs::read_to_string(key_file)?;
-        let key = PrivateKey::from_openssh(key_data)?;
+    pub fn find_identity(&mut self, public_key: &PublicKey) -> Result<SshIdentity> {
+        let key_blob = public_key.to_bytes()?;
 
         for k in self.list_keys()? {
-            if "ssh-ed25519" == k.algorithm {
-                if let Some(key) = key.public_key().key_data().ed25519() {
-                    if k.pub_key == key.as_ref() {
-                        return Ok(k);
-                    }
-                }
+            if k.key_blob == key_blob {
+                return Ok(k);
             }
         }
 
@@ -249,8 +263,13 @@ impl SshAgentClient {
 
     // https://www.agwa.name/blog/post/ssh_signatures
     // https://cvsweb.openbsd.org/src/usr.bin/ssh/PROTOCOL.sshsig?annotate=HEAD
-    pub fn sign(&mut self, identity: &SshIdentity, data: &[u8]) -> Result<Vec<u8>> {
-        self.send_signing_request(identity, data)?;
+    pub fn sign(
+        &mut self,
+        identity: &SshIdentity,
+        namespace: &str,
+        data: &[u8],
+    ) -> Result<Vec<u8>> {
+        self.send_signing_request(identity, namespace, data)?;
 
         self.read_u32()?; // answer len
         let ans_msg_id = self.read_u8()?;
@@ -259,24 +278,88 @@ impl SshAgentClient {
             return Err(Error::SShInvalidMessageId(ans_msg_id));
         }
 
-        self.read_u32()?; // msg len
-        self.read_string()?; // alg
+        //
+        // algorithm and signature, security keys append the flags and the
+        // counter the token signed
+        //
         let sign = self.read_buffer()?;
 
         info!("{}", pretty_hex::pretty_hex(&sign));
 
-        Ok(sign.to_vec())
+        let sign = Signature::try_from(sign.as_slice())?;
+
+        Ok(sign.as_bytes().to_vec())
     }
 }
 
 #[cfg(test)]
 mod tests {
 
+    use std::{
+        process::{Child, Command, Stdio},
+        thread::sleep,
+        time::Duration,
+    };
+
     use home::home_dir;
     use log::warn;
+    use ssh_key::PrivateKey;
+
+    use crate::{
+        common::{
+            r#const::DEFAULT_NAMESPACE,
+            testing::{generate_test_keys, write_test_keys, SSH_KEYGEN_TYPES},
+        },
+        key::{keys::Verifier, ssh::ssh_public::SshPublic},
+    };
 
     use super::*;
 
+    struct TestAgent {
+        child: Child,
+    }
+
+    impl Drop for TestAgent {
+        fn drop(&mut self) {
+            let _ = self.child.kill();
+            let _ = self.child.wait();
+        }
+    }
+
+    //
+    // private ssh-agent so the test doesn't depend on the user's session
+    //
+    fn start_agent(socket: &Path, key_files: &[&Path]) -> Option<TestAgent> {
+        let child = Command::new("ssh-agent")
+            .arg("-D")
+            .arg("-a")
+            .arg(socket)
+            .stdout(Stdio::null())
+            .spawn()
+            .ok()?;
+
+        let agent = TestAgent { child };
+
+        for _ in 0..50 {
+            if socket.exists() {
+                break;
+            }
+            sleep(Duration::from_millis(20));
+        }
+
+        let status = Command::new("ssh-add")
+            .env("SSH_AUTH_SOCK", socket)
+            .args(key_files)
+            .stderr(Stdio::null())
+            .status()
+            .ok()?;
+
+        match status.success() {
+            true => Some(agent),
+            false => None,
+        }
+    }
+
     #[test]
     fn test_ssh_sign() {
         let home = home_dir().unwrap();
@@ -286,14 +369,90 @@ mod tests {
         // it's possible the agent isn't running
         match SshAgentClient::new() {
             Ok(mut client) => {
-                let ident = client.find_identity(&pkey_file).unwrap();
+                let key = PrivateKey::read_openssh_file(&pkey_file).unwrap();
+                let ident = client.find_identity(key.public_key()).unwrap();
 
                 let message = b"Hello, World!";
-                let signature = client.sign(&ident, message).unwrap();
+                let signature = client.sign(&ident, DEFAULT_NAMESPACE, message).unwrap();
 
                 info!("{:?}", hex::encode(signature));
             }
             Err(e) => warn!("Error: {e}"),
         }
     }
+
+    #[test]
+    fn test_ssh_agent_round_trip() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let (private_key, public_key) = write_test_keys(&tmp_dir);
+        let socket = tmp_dir.path().join("agent.sock");
+
+        let _agent = match start_agent(&socket, &[&private_key]) {
+            Some(v) => v,
+            None => {
+                warn!("unable to start ssh-agent");
+                return;
+            }
+        };
+
+        let mut client = SshAgentClient::new_with_socket(&socket).unwrap();
+        let key = PrivateKey::read_openssh_file(&private_key).unwrap();
+        let ident = client.find_identity(key.public_key()).unwrap();
+
+        let message = b"Hello, World!";
+        let signature = client.sign(&ident, DEFAULT_NAMESPACE, message).unwrap();
+
+        let verifier = SshPublic::new(&public_key).unwrap();
+
+        verifier
+            .verify(DEFAULT_NAMESPACE, message, &signature)
+            .unwrap();
+
+        let ret = verifier.verify("other", message, &signature);
+        assert!(ret.is_err());
+    }
+
+    #[test]
+    fn test_ssh_agent_algorithms() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let socket = tmp_dir.path().join("agent.sock");
+
+        let mut keys = vec![];
+
+        for (i, args) in SSH_KEYGEN_TYPES.iter().enumerate() {
+            match generate_test_keys(&tmp_dir, &format!("key{i}"), args) {
+                Some(v) => keys.push(v),
+                None => {
+                    warn!("unable to run ssh-keygen");
+                    return;
+                }
+            }
+        }
+
+        let key_files: Vec<&Path> = keys.iter().map(|(k, _)| k.as_path()).collect();
+
+        let _agent = match start_agent(&socket, &key_files) {
+            Some(v) => v,
+            None => {
+                warn!("unable to start ssh-agent");
+                return;
+            }
+        };
+
+        let mut client = SshAgentClient::new_with_socket(&socket).unwrap();
+
+        for (_, public_key) in &keys {
+            let key = PublicKey::read_openssh_file(public_key).unwrap();
+            let ident = client.find_identity(&key).unwrap();
+
+            let message = b"Hello, World!";
+            let signature = client.sign(&ident, DEFAULT_NAMESPACE, message).unwrap();
+
+            let verifier = SshPublic::new(public_key).unwrap();
+
+            verifier
+                .verify(DEFAULT_NAMESPACE, message, &signature)
+                .unwrap();
+        }
+    }
 }
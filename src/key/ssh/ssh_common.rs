@@ -0,0 +1,114 @@
+use ssh_key::{Algorithm, HashAlg};
+
+//
+// an RSA key can sign with several hashes, signatures are always made and
+// expected with rsa-sha2-512
+//
+pub fn signature_algorithm(key_algorithm: Algorithm) -> Algorithm {
+    match key_algorithm {
+        Algorithm::Rsa { .. } => Algorithm::Rsa {
+            hash: Some(HashAlg::Sha512),
+        },
+        a => a,
+    }
+}
+
+//
+// FIDO private key files only hold a handle to the key living on the token,
+// signing has to go through the agent
+//
+pub fn is_security_key(algorithm: &Algorithm) -> bool {
+    matches!(
+        algorithm,
+        Algorithm::SkEd25519 | Algorithm::SkEcdsaSha2NistP256
+    )
+}
+
+const OPENSSH_KEY_MAGIC: &[u8] = b"openssh-key-v1\0";
+const OPENSSH_BLOCK_SIZE: usize = 8;
+
+fn read_u32(data: &mut &[u8]) -> Option<u32> {
+    let (v, rest) = data.split_first_chunk::<4>()?;
+    *data = rest;
+    Some(u32::from_be_bytes(*v))
+}
+
+fn read_string<'a>(data: &mut &'a [u8]) -> Option<&'a [u8]> {
+    let len = read_u32(data)? as usize;
+    let (v, rest) = data.split_at_checked(len)?;
+    *data = rest;
+    Some(v)
+}
+
+fn write_string(out: &mut Vec<u8>, data: &[u8]) {
+    out.extend((data.len() as u32).to_be_bytes());
+    out.extend(data);
+}
+
+//
+// OpenSSH writes the ECDSA private scalar as a minimal mpint, ssh-key only
+// accepts it padded to the field size. About half the P-521 keys have a short
+// scalar. Returns the binary key with the scalar padded, None if there is
+// nothing to fix
+//
+pub fn pad_ecdsa_private_key(encoded_key: &str) -> Option<Vec<u8>> {
+    let pem = pem::parse(encoded_key).ok()?;
+
+    let mut data = pem.contents().strip_prefix(OPENSSH_KEY_MAGIC)?;
+
+    let cipher = read_string(&mut data)?;
+    let kdf = read_string(&mut data)?;
+    let kdf_options = read_string(&mut data)?;
+    let key_count = read_u32(&mut data)?;
+    let public_key = read_string(&mut data)?;
+    let mut private = read_string(&mut data)?;
+
+    if cipher != b"none" || key_count != 1 {
+        return None;
+    }
+
+    let check = private.get(..8)?;
+    private = &private[8..];
+    let key_type = read_string(&mut private)?;
+    let curve = read_string(&mut private)?;
+    let point = read_string(&mut private)?;
+    let scalar = read_string(&mut private)?;
+    let comment = read_string(&mut private)?;
+
+    let field_size = match curve {
+        b"nistp256" => 32,
+        b"nistp384" => 48,
+        b"nistp521" => 66,
+        _ => return None,
+    };
+
+    if scalar.len() >= field_size {
+        return None;
+    }
+
+    let mut padded = vec![0u8; field_size - scalar.len()];
+    padded.extend(scalar);
+
+    let mut new_private = check.to_vec();
+    write_string(&mut new_private, key_type);
+    write_string(&mut new_private, curve);
+    write_string(&mut new_private, point);
+    write_string(&mut new_private, &padded);
+    write_string(&mut new_private, comment);
+
+    let mut pad = 1u8;
+    while new_private.len() % OPENSSH_BLOCK_SIZE != 0 {
+        new_private.push(pad);
+        pad += 1;
+    }
+
+    let mut key = OPENSSH_KEY_MAGIC.to_vec();
+    write_string(&mut key, cipher);
+    write_string(&mut key, kdf);
+    write_string(&mut key, kdf_options);
+    key.extend(key_count.to_be_bytes());
+    write_string(&mut key, public_key);
+    write_string(&mut key, &new_private);
+
+    Some(key)
+}
@@ -7,7 +7,7 @@ use crate::{
     key::keys::Verifier,
 };
 
-use super::ssh_agent::DV_NS_STR;
+use super::ssh_common::signature_algorithm;
 
 #[derive(Debug)]
 pub struct SshPublic {
@@ -15,17 +15,36 @@ pub struct SshPublic {
 }
 
 impl Verifier for SshPublic {
-    fn verify(&self, msg: &[u8], signature: &[u8]) -> Result<()> {
-        let sig = Signature::new(self.pub_key.algorithm(), signature)?;
+    fn verify(&self, namespace: &str, msg: &[u8], signature: &[u8]) -> Result<()> {
+        let algorithm = signature_algorithm(self.pub_key.algorithm());
+
+        // a signature that doesn't even parse is as invalid as a wrong one
+        let sig = match Signature::new(algorithm, signature) {
+            Ok(v) => v,
+            Err(_) => return Err(Error::VerificationFailure),
+        };
 
         let ssh_sig = SshSig::new(
             self.pub_key.key_data().clone(),
-            DV_NS_STR,
+            namespace,
             HashAlg::Sha512,
             sig,
         )?;
 
-        match self.pub_key.verify(DV_NS_STR, msg, &ssh_sig) {
+        self.verify_sshsig(namespace, msg, &ssh_sig)
+    }
+
+    fn fingerprint(&self) -> Option<String> {
+        let fingerprint = self.pub_key.fingerprint(HashAlg::Sha256);
+        Some(fingerprint.to_string())
+    }
+
+    fn verify_sshsig(&self, namespace: &str, msg: &[u8], signature: &SshSig) -> Result<()> {
+        if signature.public_key() != self.pub_key.key_data() {
+            return Err(Error::VerificationFailure);
+        }
+
+        match self.pub_key.verify(namespace, msg, signature) {
             Ok(_) => Ok(()),
             Err(_) => Err(Error::VerificationFailure),
         }
@@ -40,4 +59,42 @@ impl SshPublic {
 
         Ok(SshPublic { pub_key })
     }
+
+    pub fn from_key(pub_key: PublicKey) -> SshPublic {
+        SshPublic { pub_key }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    // signature made by a FIDO token, from the ssh-key test vectors
+    const SK_ED25519_KEY: &str = "sk-ssh-ed25519@openssh.com AAAAGnNrLXNzaC1lZDI1NTE5QG9wZW5zc2guY29tAAAAINSoElFleH+nN83FoLqqepJjN+y7Gs5lrn7qXjBqQZyuAAAABHNzaDo= user@example.com";
+
+    const SK_ED25519_SIGNATURE: &str = "-----BEGIN SSH SIGNATURE-----
+U1NIU0lHAAAAAQAAAEoAAAAac2stc3NoLWVkMjU1MTlAb3BlbnNzaC5jb20AAAAg1KgSUW
+V4f6c3zcWguqp6kmM37LsazmWufupeMGpBnK4AAAAEc3NoOgAAAAdleGFtcGxlAAAAAAAA
+AAZzaGE1MTIAAABnAAAAGnNrLXNzaC1lZDI1NTE5QG9wZW5zc2guY29tAAAAQC9WcLb5NG
+XRdCOHinQIS/MxdnAx7SQMYnyOt5q4+huTWh/Zk/UvWhP+wXl/ikNPlDpgliRq6o3VyKqS
+LLo9lQYBAAAACQ==
+-----END SSH SIGNATURE-----
+";
+
+    #[test]
+    fn verify_security_key() {
+        let pub_key = PublicKey::from_openssh(SK_ED25519_KEY).unwrap();
+        let v = SshPublic::from_key(pub_key);
+
+        let sig = SshSig::from_pem(SK_ED25519_SIGNATURE).unwrap();
+
+        v.verify_sshsig("example", b"testing", &sig).unwrap();
+        assert!(v.verify_sshsig("example", b"bye", &sig).is_err());
+
+        // raw signature as stored in the dver envelope
+        let raw = sig.signature().as_bytes();
+        v.verify("example", b"testing", raw).unwrap();
+        assert!(v.verify("other", b"testing", raw).is_err());
+    }
 }
@@ -1,26 +1,48 @@
+use std::{path::PathBuf, process::exit};
+
 use dver::{
-    common::hash::DVHashType,
+    common::{
+        fmt::parse_time, hash::DVHashType, r#const::DEFAULT_NAMESPACE, report::DVOutputFormat,
+    },
+    directory::{attributes::DVAttribute, special::DVSpecialPolicy},
+    key::key_type::DVKeyType,
     logging::init_logging,
-    sign::sign_dir::{sign_directory, DVSignType},
-    verify::verify_dir::verify_directory,
+    sign::{
+        cosign::{cosign_signature, DVCosignOptions},
+        sign_dir::{sign_directory, DVSignFormat, DVSignOptions, DVSignType},
+        sign_file::{sign_file, DVSignFileOptions},
+    },
+    verify::{
+        verify_dir::{verify_directory, DVSubtreeKey, DVVerifyOptions},
+        verify_file::{verify_file, DVVerifyFileOptions},
+    },
 };
 use structopt::StructOpt;
 
 use dver::error::Result;
 
+const ATTRIBUTES: &[&str] = &["mode", "uid", "gid", "owner", "group"];
+
 #[derive(Debug, StructOpt)]
 struct SignOpt {
-    /// Directory to sign
+    /// Directory or archive (.tar, .tar.gz, .tar.zst, .zip) to sign
     #[structopt(long, short)]
     directory: String,
     /// Private key file path
     #[structopt(long, short = "k")]
     private_key: String,
+    /// Key type, detected from the key file by default
+    #[structopt(long, possible_values = &["ssh", "pgp", "pkcs8"])]
+    key_type: Option<DVKeyType>,
     /// Output Signature File
     #[structopt(long = "output", short = "o")]
-    signature_file: Option<String>,
+    signature_file: Option<PathBuf>,
     /// Hashing Algorithm
-    #[structopt(long,default_value="sha256", possible_values = &["sha256", "sha512"])]
+    #[structopt(
+        long,
+        default_value = "sha256",
+        possible_values = &["sha256", "sha512", "sha3-256", "sha3-512", "blake3"]
+    )]
     hash_type: DVHashType,
     /// Verbose
     #[structopt(long, short)]
@@ -28,28 +50,203 @@ struct SignOpt {
     /// Include context in the signature to help troubleshooting
     #[structopt(long, default_value="complete", possible_values = &["short", "complete"])]
     signature_type: DVSignType,
+    /// dver envelope or standard OpenSSH signature
+    #[structopt(long, default_value="dver", possible_values = &["dver", "sshsig"])]
+    signature_format: DVSignFormat,
     /// Files directories to ignore
     #[structopt(long = "exclude", short = "e")]
     exclude_list: Vec<String>,
+    /// Attributes to record on top of the content
+    #[structopt(long, use_delimiter = true, possible_values = ATTRIBUTES)]
+    attributes: Vec<DVAttribute>,
+    /// Extended attribute namespaces to record, security.selinux for instance
+    #[structopt(long, use_delimiter = true)]
+    xattrs: Vec<String>,
+    /// Walk the targets of symlinks instead of recording the links
+    #[structopt(long)]
+    follow_symlinks: bool,
+    /// Sockets, fifos and device nodes
+    #[structopt(long, default_value = "reject", possible_values = &["record", "skip", "reject"])]
+    special_files: DVSpecialPolicy,
+    /// Subdirectory signed on its own, only its root hash is recorded
+    #[structopt(long = "subtree")]
+    subtrees: Vec<PathBuf>,
+    /// Not valid before, @<unix time>, +<n>[smhd], YYYY-MM-DD or RFC 3339
+    #[structopt(long, parse(try_from_str = parse_time))]
+    not_before: Option<u64>,
+    /// Not valid from then on, @<unix time>, +<n>[smhd], YYYY-MM-DD or RFC 3339
+    #[structopt(long, parse(try_from_str = parse_time))]
+    expires: Option<u64>,
+    /// Hashing threads, one per core by default
+    #[structopt(long, short, default_value = "0")]
+    jobs: usize,
+    /// Don't reuse the file hashes of the previous sign
+    #[structopt(long)]
+    no_cache: bool,
+    /// Rehash every file, stale cache entries are reported
+    #[structopt(long, conflicts_with = "no-cache")]
+    paranoid: bool,
+    /// Output format
+    #[structopt(long, default_value="text", possible_values = &["text", "json"])]
+    format: DVOutputFormat,
+    /// Signature namespace
+    #[structopt(long, default_value = DEFAULT_NAMESPACE)]
+    namespace: String,
 }
 
 #[derive(Debug, StructOpt)]
 struct VerifyOpt {
-    /// Directory to verify
+    /// Directory or archive (.tar, .tar.gz, .tar.zst, .zip) to verify
     #[structopt(long, short)]
     directory: String,
-    /// Public key file path
-    #[structopt(long, short = "k")]
-    public_key: String,
+    /// Public key file path, can be repeated
+    #[structopt(long, short = "k", alias = "key", required_unless = "allowed-signers")]
+    public_key: Vec<String>,
+    /// Number of different trusted keys that have to have signed
+    #[structopt(long, default_value = "1")]
+    threshold: usize,
+    /// Key type, detected from the key file by default
+    #[structopt(long, possible_values = &["ssh", "pgp", "pkcs8"])]
+    key_type: Option<DVKeyType>,
+    /// OpenSSH allowed_signers file
+    #[structopt(long)]
+    allowed_signers: Option<PathBuf>,
     /// Input Signature File
     #[structopt(long = "input", short = "i")]
-    signature_file: Option<String>,
+    signature_file: Option<PathBuf>,
+    /// Expected Hashing Algorithm, taken from the signature by default
+    #[structopt(long, possible_values = &["sha256", "sha512", "sha3-256", "sha3-512", "blake3"])]
+    hash_type: Option<DVHashType>,
+    /// Only verify this file or subdirectory, relative to the directory
+    #[structopt(long)]
+    path: Option<PathBuf>,
+    /// Recorded attributes to enforce, all of them by default
+    #[structopt(long, use_delimiter = true, possible_values = ATTRIBUTES)]
+    enforce: Option<Vec<DVAttribute>>,
+    /// Key of a signed subtree, <subtree>=<public key>
+    #[structopt(long = "subtree-key")]
+    subtree_keys: Vec<DVSubtreeKey>,
+    /// Verify as of this time instead of now, same formats as --expires
+    #[structopt(long, parse(try_from_str = parse_time))]
+    at: Option<u64>,
+    /// Hashing threads, one per core by default
+    #[structopt(long, short, default_value = "0")]
+    jobs: usize,
+    /// Verbose
+    #[structopt(long, short)]
+    verbose: bool,
+    /// Output format
+    #[structopt(long, default_value="text", possible_values = &["text", "json"])]
+    format: DVOutputFormat,
+    /// Signature namespace
+    #[structopt(long, default_value = DEFAULT_NAMESPACE)]
+    namespace: String,
+}
+
+#[derive(Debug, StructOpt)]
+struct SignFileOpt {
+    /// File to sign, - for stdin
+    #[structopt(long, short)]
+    file: String,
+    /// Private key file path
+    #[structopt(long, short = "k")]
+    private_key: String,
+    /// Key type, detected from the key file by default
+    #[structopt(long, possible_values = &["ssh", "pgp", "pkcs8"])]
+    key_type: Option<DVKeyType>,
+    /// Output Signature File, <file>.dver.sig by default
+    #[structopt(long = "output", short = "o")]
+    signature_file: Option<PathBuf>,
     /// Hashing Algorithm
-    #[structopt(long,default_value="sha256", possible_values = &["sha256", "sha512"])]
+    #[structopt(
+        long,
+        default_value = "sha256",
+        possible_values = &["sha256", "sha512", "sha3-256", "sha3-512", "blake3"]
+    )]
     hash_type: DVHashType,
     /// Verbose
     #[structopt(long, short)]
     verbose: bool,
+    /// dver envelope or standard OpenSSH signature
+    #[structopt(long, default_value="dver", possible_values = &["dver", "sshsig"])]
+    signature_format: DVSignFormat,
+    /// Not valid before, @<unix time>, +<n>[smhd], YYYY-MM-DD or RFC 3339
+    #[structopt(long, parse(try_from_str = parse_time))]
+    not_before: Option<u64>,
+    /// Not valid from then on, @<unix time>, +<n>[smhd], YYYY-MM-DD or RFC 3339
+    #[structopt(long, parse(try_from_str = parse_time))]
+    expires: Option<u64>,
+    /// Output format
+    #[structopt(long, default_value="text", possible_values = &["text", "json"])]
+    format: DVOutputFormat,
+    /// Signature namespace
+    #[structopt(long, default_value = DEFAULT_NAMESPACE)]
+    namespace: String,
+}
+
+#[derive(Debug, StructOpt)]
+struct VerifyFileOpt {
+    /// File to verify, - for stdin
+    #[structopt(long, short)]
+    file: String,
+    /// Public key file path, can be repeated
+    #[structopt(long, short = "k", alias = "key", required_unless = "allowed-signers")]
+    public_key: Vec<String>,
+    /// Number of different trusted keys that have to have signed
+    #[structopt(long, default_value = "1")]
+    threshold: usize,
+    /// Key type, detected from the key file by default
+    #[structopt(long, possible_values = &["ssh", "pgp", "pkcs8"])]
+    key_type: Option<DVKeyType>,
+    /// OpenSSH allowed_signers file
+    #[structopt(long)]
+    allowed_signers: Option<PathBuf>,
+    /// Input Signature File, <file>.dver.sig by default
+    #[structopt(long = "input", short = "i")]
+    signature_file: Option<PathBuf>,
+    /// Expected Hashing Algorithm, taken from the signature by default
+    #[structopt(long, possible_values = &["sha256", "sha512", "sha3-256", "sha3-512", "blake3"])]
+    hash_type: Option<DVHashType>,
+    /// Verify as of this time instead of now, same formats as --expires
+    #[structopt(long, parse(try_from_str = parse_time))]
+    at: Option<u64>,
+    /// Verbose
+    #[structopt(long, short)]
+    verbose: bool,
+    /// Output format
+    #[structopt(long, default_value="text", possible_values = &["text", "json"])]
+    format: DVOutputFormat,
+    /// Signature namespace
+    #[structopt(long, default_value = DEFAULT_NAMESPACE)]
+    namespace: String,
+}
+
+#[derive(Debug, StructOpt)]
+struct CosignOpt {
+    /// Signature file to add a signature to
+    #[structopt(long = "input", short = "i")]
+    signature_file: PathBuf,
+    /// Private key file path
+    #[structopt(long, short = "k")]
+    private_key: String,
+    /// Public key the signature has to check out with, can be repeated
+    #[structopt(long, short = "p", required_unless = "allowed-signers")]
+    public_key: Vec<String>,
+    /// OpenSSH allowed_signers file
+    #[structopt(long)]
+    allowed_signers: Option<PathBuf>,
+    /// Key type, detected from the key file by default
+    #[structopt(long, possible_values = &["ssh", "pgp", "pkcs8"])]
+    key_type: Option<DVKeyType>,
+    /// Verbose
+    #[structopt(long, short)]
+    verbose: bool,
+    /// Output format
+    #[structopt(long, default_value="text", possible_values = &["text", "json"])]
+    format: DVOutputFormat,
+    /// Signature namespace
+    #[structopt(long, default_value = DEFAULT_NAMESPACE)]
+    namespace: String,
 }
 
 #[derive(Debug, StructOpt)]
@@ -59,14 +256,24 @@ enum DVCommand {
     Sign(SignOpt),
     /// Verify a deployment directory
     Verify(VerifyOpt),
+    /// Sign a single file, detached signature
+    SignFile(SignFileOpt),
+    /// Verify a single file
+    VerifyFile(VerifyFileOpt),
+    /// Add a signature to a signature file
+    #[structopt(alias = "add-signature")]
+    Cosign(CosignOpt),
 }
 
-fn main() -> Result<()> {
+fn run() -> Result<()> {
     let opt = DVCommand::from_args();
 
     let verbose = match &opt {
         DVCommand::Sign(opt) => opt.verbose,
         DVCommand::Verify(opt) => opt.verbose,
+        DVCommand::SignFile(opt) => opt.verbose,
+        DVCommand::VerifyFile(opt) => opt.verbose,
+        DVCommand::Cosign(opt) => opt.verbose,
     };
 
     if verbose {
@@ -74,19 +281,100 @@ fn main() -> Result<()> {
     }
 
     match opt {
-        DVCommand::Sign(opt) => sign_directory(
-            opt.directory,
-            opt.private_key,
-            opt.hash_type,
-            opt.signature_file,
-            opt.signature_type,
-            opt.exclude_list,
-        ),
-        DVCommand::Verify(opt) => verify_directory(
-            opt.directory,
-            opt.public_key,
-            opt.hash_type,
-            opt.signature_file,
-        ),
+        DVCommand::Sign(opt) => {
+            let sign_opts = DVSignOptions {
+                key_type: opt.key_type,
+                hash_type: opt.hash_type,
+                signature_file: opt.signature_file,
+                signature_type: opt.signature_type,
+                signature_format: opt.signature_format,
+                exclude_list: opt.exclude_list,
+                attributes: opt.attributes,
+                xattrs: opt.xattrs,
+                follow_symlinks: opt.follow_symlinks,
+                special_files: opt.special_files,
+                subtrees: opt.subtrees,
+                not_before: opt.not_before,
+                expires: opt.expires,
+                jobs: opt.jobs,
+                cache: !opt.no_cache,
+                paranoid: opt.paranoid,
+                namespace: opt.namespace,
+                format: opt.format,
+            };
+            sign_directory(opt.directory, opt.private_key, &sign_opts)
+        }
+        DVCommand::Verify(opt) => {
+            let mut public_keys = opt.public_key.into_iter();
+            let public_key = public_keys.next();
+
+            let verify_opts = DVVerifyOptions {
+                key_type: opt.key_type,
+                allowed_signers: opt.allowed_signers,
+                hash_type: opt.hash_type,
+                signature_file: opt.signature_file,
+                path: opt.path,
+                enforce: opt.enforce,
+                subtree_keys: opt.subtree_keys,
+                public_keys: public_keys.collect(),
+                threshold: opt.threshold,
+                at: opt.at,
+                jobs: opt.jobs,
+                namespace: opt.namespace,
+                format: opt.format,
+            };
+            verify_directory(opt.directory, public_key, &verify_opts)
+        }
+        DVCommand::SignFile(opt) => {
+            let sign_opts = DVSignFileOptions {
+                key_type: opt.key_type,
+                hash_type: opt.hash_type,
+                signature_file: opt.signature_file,
+                signature_format: opt.signature_format,
+                not_before: opt.not_before,
+                expires: opt.expires,
+                namespace: opt.namespace,
+                format: opt.format,
+            };
+            sign_file(opt.file, opt.private_key, &sign_opts)
+        }
+        DVCommand::VerifyFile(opt) => {
+            let mut public_keys = opt.public_key.into_iter();
+            let public_key = public_keys.next();
+
+            let verify_opts = DVVerifyFileOptions {
+                key_type: opt.key_type,
+                allowed_signers: opt.allowed_signers,
+                hash_type: opt.hash_type,
+                signature_file: opt.signature_file,
+                public_keys: public_keys.collect(),
+                threshold: opt.threshold,
+                at: opt.at,
+                namespace: opt.namespace,
+                format: opt.format,
+            };
+            verify_file(opt.file, public_key, &verify_opts)
+        }
+        DVCommand::Cosign(opt) => {
+            let cosign_opts = DVCosignOptions {
+                key_type: opt.key_type,
+                public_keys: opt.public_key,
+                allowed_signers: opt.allowed_signers,
+                namespace: opt.namespace,
+                format: opt.format,
+            };
+            cosign_signature(opt.signature_file, opt.private_key, &cosign_opts)
+        }
+    }
+}
+
+//
+// 1: usage or i/o error, 2: invalid signature, 3: directory or file modified,
+// 4: only the permissions changed
+//
+fn main() {
+    if let Err(e) = run() {
+        eprintln!("Error: {e:?}");
+        exit(e.exit_code());
     }
 }
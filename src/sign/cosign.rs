@@ -0,0 +1,129 @@
+use std::path::{Path, PathBuf};
+
+use crate::{
+    common::{
+        fmt::fmt_file_size,
+        r#const::DEFAULT_NAMESPACE,
+        report::{DVOutputFormat, Report},
+    },
+    directory::walker::Walker,
+    error::{Error, Result},
+    key::{
+        key_type::DVKeyType,
+        keys::{load_private_key, load_trusted_keys},
+    },
+    sign::{sign_dir::DVSignature, sign_file::DVFileManifest},
+    verify::verify_dir::{verification_time, verify_signature},
+};
+
+#[derive(Debug)]
+pub struct DVCosignOptions {
+    pub key_type: Option<DVKeyType>,
+    // the signature there has to check out with one of them
+    pub public_keys: Vec<String>,
+    pub allowed_signers: Option<PathBuf>,
+    pub namespace: String,
+    pub format: DVOutputFormat,
+}
+
+impl Default for DVCosignOptions {
+    fn default() -> Self {
+        DVCosignOptions {
+            key_type: None,
+            public_keys: Vec::new(),
+            allowed_signers: None,
+            namespace: DEFAULT_NAMESPACE.into(),
+            format: DVOutputFormat::Text,
+        }
+    }
+}
+
+//
+// what the signature covers. Short signatures made before format 12 only keep
+// the header, what was signed is gone and can't be signed again
+//
+fn report_content(report: &mut Report, s: &DVSignature) -> Result<()> {
+    if let Ok(manifest) = DVFileManifest::decode(s.content()) {
+        report.add("Hash", hex::encode(&manifest.hash));
+        return Ok(());
+    }
+
+    let walker = match s.content().is_empty() {
+        true => None,
+        false => Some(Walker::decode(s.content())?),
+    };
+
+    match walker.as_ref().and_then(|w| w.root_hash()) {
+        Some(root_hash) => report.add("Root Hash", hex::encode(root_hash)),
+        None => return Err(Error::InvalidArgument("cosign needs a root hash".into())),
+    }
+
+    Ok(())
+}
+
+fn cosign<P: AsRef<Path>>(
+    report: &mut Report,
+    signature_file: P,
+    private_key: String,
+    opts: &DVCosignOptions,
+) -> Result<()> {
+    let signature_file = signature_file.as_ref();
+
+    report.add("Signature File", signature_file.display());
+    report.add("Private Key", &private_key);
+
+    let keys = load_trusted_keys(
+        &opts.public_keys,
+        opts.key_type,
+        opts.allowed_signers.as_ref(),
+    )?;
+
+    let mut s = DVSignature::from_file(signature_file)?;
+
+    report.add("Signature Format", s.format());
+
+    report_content(report, &s)?;
+
+    // the allowed signers have to be valid now, like for verify
+    let verified_at = verification_time(None)?;
+
+    //
+    // a cosignature vouches for what was signed, only what a trusted key
+    // already signed is signed again
+    //
+    let content = s.content();
+    verify_signature(report, &s, &keys, &opts.namespace, content, 1, verified_at)?;
+
+    let mut key = load_private_key(private_key, opts.key_type)?;
+
+    if let Some(fingerprint) = key.fingerprint() {
+        report.add("Fingerprint", fingerprint);
+    }
+
+    s.cosign(key.as_mut(), &opts.namespace)?;
+
+    s.to_file(signature_file)?;
+
+    report.add("Signatures", s.signatures().count());
+    report.add("File Size", fmt_file_size(signature_file));
+
+    Ok(())
+}
+
+//
+// adds a signature to a directory or file signature, the ones already there
+// stay valid. One of them has to have been made by a trusted key
+//
+pub fn cosign_signature<P: AsRef<Path>>(
+    signature_file: P,
+    private_key: String,
+    opts: &DVCosignOptions,
+) -> Result<()> {
+    let mut report = Report::new(opts.format, "Cosigning");
+
+    let ret = cosign(&mut report, signature_file, private_key, opts);
+
+    report.finish(&ret);
+
+    ret
+}
@@ -1 +1,3 @@
+pub mod cosign;
 pub mod sign_dir;
+pub mod sign_file;
@@ -2,28 +2,42 @@ use core::fmt;
 use std::{
     fs::{self, canonicalize, File},
     io::Write,
-    path::Path,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
     vec,
 };
 
 use crate::{
     common::{
-        fmt::{fmt_file_size, printkv},
+        fmt::{fmt_file_size, fmt_symlinks, fmt_timestamp},
         hash::{hash_string, DVHashType},
-        r#const::DEFAULT_SIGN_FILE_NAME,
+        r#const::{DEFAULT_NAMESPACE, TOOL_VERSION},
+        report::{DVOutputFormat, Report},
+    },
+    directory::{
+        archive::{archive_type, default_signature_file},
+        attributes::DVAttribute,
+        cache::HashCache,
+        special::DVSpecialPolicy,
+        walker::{normalize_path, Walker, WalkerHeader},
     },
-    directory::walker::Walker,
     error::Error,
-    key::keys::load_private_key,
+    key::{
+        key_type::DVKeyType,
+        keys::{load_private_key, Signer, Verifier},
+    },
 };
 
-use crate::common::serializer::{base64_deserializer, base64_serializer};
+use crate::common::serializer::{
+    base64_deserializer, base64_list_deserializer, base64_list_serializer, base64_serializer,
+};
 
 use base64::{prelude::BASE64_STANDARD, Engine};
 use log::{info, warn};
 use serde_derive::{Deserialize, Serialize};
+use ssh_key::{HashAlg, LineEnding, SshSig};
 
-#[derive(Debug)]
+#[derive(Debug, Copy, Clone)]
 pub enum DVSignType {
     Short = 0,
     Complete = 1,
@@ -50,8 +64,51 @@ impl fmt::Display for DVSignType {
     }
 }
 
+//
+// dver:   our own envelope, a json document holding the manifest and the
+//         signature
+// sshsig: a standard OpenSSH signature over the manifest followed by the
+//         manifest itself, ssh-keygen -Y verify ignores what follows the
+//         signature
+//
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub enum DVSignFormat {
+    #[default]
+    Dver,
+    SshSig,
+}
+
+impl std::str::FromStr for DVSignFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "dver" => Ok(DVSignFormat::Dver),
+            "sshsig" => Ok(DVSignFormat::SshSig),
+            _ => Err(Error::UnknownSignatureFormat),
+        }
+    }
+}
+
+impl fmt::Display for DVSignFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DVSignFormat::Dver => write!(f, "dver"),
+            DVSignFormat::SshSig => write!(f, "sshsig"),
+        }
+    }
+}
+
 use crate::error::Result;
 
+const PEM_SIGNATURE: &str = "SIGNATURE ";
+const PEM_SSH_SIGNATURE_BEGIN: &str = "-----BEGIN SSH SIGNATURE-----";
+const PEM_SSH_SIGNATURE_END: &str = "-----END SSH SIGNATURE-----";
+const PEM_MANIFEST: &str = "DVER MANIFEST";
+
+// last format writing short signatures without the json envelope
+const BARE_SIGNATURE_FORMAT_VER: u8 = 1;
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct DVSignature {
     content: String,
@@ -60,6 +117,16 @@ pub struct DVSignature {
         deserialize_with = "base64_deserializer"
     )]
     pub signature: Vec<u8>,
+    // more signatures over the same content, added by cosign
+    #[serde(
+        default,
+        skip_serializing_if = "Vec::is_empty",
+        serialize_with = "base64_list_serializer",
+        deserialize_with = "base64_list_deserializer"
+    )]
+    pub cosignatures: Vec<Vec<u8>>,
+    #[serde(skip)]
+    format: DVSignFormat,
 }
 
 impl Default for DVSignature {
@@ -68,61 +135,119 @@ impl Default for DVSignature {
     }
 }
 
+fn write_pem(fd: &mut File, label: &str, base64_data: &str) -> Result<()> {
+    fd.write_all(format!("-----BEGIN {label}-----\n").as_bytes())?;
+
+    for line in textwrap::wrap(base64_data, 64) {
+        fd.write_all(line.as_bytes())?;
+        fd.write_all(b"\n")?;
+    }
+
+    fd.write_all(format!("-----END {label}-----\n").as_bytes())?;
+
+    Ok(())
+}
+
 impl DVSignature {
     pub fn new() -> DVSignature {
         DVSignature {
             content: String::new(),
             signature: vec![],
+            cosignatures: vec![],
+            format: DVSignFormat::Dver,
         }
     }
 
+    //
+    // the cosignatures follow the first signature, ssh-keygen only reads the
+    // first one
+    //
+    fn from_sshsig(data: &str) -> Result<DVSignature> {
+        let mut armors = Vec::new();
+        let mut manifest = data;
+
+        while manifest.trim_start().starts_with(PEM_SSH_SIGNATURE_BEGIN) {
+            let (armor, rest) = match manifest.find(PEM_SSH_SIGNATURE_END) {
+                Some(v) => manifest.split_at(v + PEM_SSH_SIGNATURE_END.len()),
+                None => return Err(Error::InvalidArgument("invalid ssh signature".into())),
+            };
+
+            // make sure it parses before going any further
+            SshSig::from_pem(armor.trim_start())?;
+
+            armors.push(armor.trim_start().as_bytes().to_vec());
+            manifest = rest;
+        }
+
+        if armors.is_empty() {
+            return Err(Error::InvalidArgument("invalid ssh signature".into()));
+        }
+
+        let signature = armors.remove(0);
+
+        let content = match manifest.trim().is_empty() {
+            true => String::new(),
+            false => BASE64_STANDARD.encode(pem::parse(manifest)?.contents()),
+        };
+
+        Ok(DVSignature {
+            content,
+            signature,
+            cosignatures: armors,
+            format: DVSignFormat::SshSig,
+        })
+    }
+
     pub fn from_file<P: AsRef<Path>>(signature_file: P) -> Result<DVSignature> {
-        let b64_data = fs::read_to_string(&signature_file)?;
+        let data = fs::read(&signature_file)?;
+
+        DVSignature::parse(data).map_err(Error::invalid_signature)
+    }
+
+    fn parse(data: Vec<u8>) -> Result<DVSignature> {
+        let b64_data = String::from_utf8(data)?;
+
+        if b64_data.trim_start().starts_with(PEM_SSH_SIGNATURE_BEGIN) {
+            return DVSignature::from_sshsig(b64_data.trim_start());
+        }
 
         let pem = pem::parse(b64_data)?;
 
         //
-        // this doesn't feel right, but would it be better to let
-        // serde_json::from_slice() fail since we're not loading json data
-        //
-        // will revisit
+        // short signatures used to be the bare signature, without the header
+        // needed to rebuild the manifest, they can't be verified anymore
         //
-        let s = match pem.contents()[0] {
-            b'{' => {
+        let s = match pem.contents().first() {
+            Some(b'{') => {
                 let s: DVSignature = serde_json::from_slice(pem.contents())?;
                 s
             }
-            _ => {
-                let signature = pem.contents().to_vec();
-                DVSignature {
-                    content: String::new(),
-                    signature,
-                }
-            }
+            _ => return Err(Error::UnsupportedFormatVersion(BARE_SIGNATURE_FORMAT_VER)),
         };
 
         Ok(s)
     }
 
-    pub fn to_file<P: AsRef<Path>>(&self, file_path: P, signature_type: DVSignType) -> Result<()> {
-        let signature_data = match signature_type {
-            DVSignType::Complete => &serde_json::to_vec(self)?,
-            DVSignType::Short => &self.signature,
-        };
-
-        let base64_data = BASE64_STANDARD.encode(signature_data);
-
+    pub fn to_file<P: AsRef<Path>>(&self, file_path: P) -> Result<()> {
         let mut fd = File::create(file_path)?;
 
-        fd.write_all(b"-----BEGIN SIGNATURE -----\n")?;
-
-        for line in textwrap::wrap(&base64_data, 64) {
-            fd.write_all(line.as_bytes())?;
-            fd.write_all(b"\n")?;
+        match self.format {
+            DVSignFormat::Dver => {
+                let signature_data = serde_json::to_vec(self)?;
+                let base64_data = BASE64_STANDARD.encode(signature_data);
+                write_pem(&mut fd, PEM_SIGNATURE, &base64_data)?;
+            }
+            DVSignFormat::SshSig => {
+                for signature in self.signatures() {
+                    fd.write_all(signature)?;
+                    if !signature.ends_with(b"\n") {
+                        fd.write_all(b"\n")?;
+                    }
+                }
+                write_pem(&mut fd, PEM_MANIFEST, &self.content)?;
+            }
         }
 
-        fd.write_all(b"-----END SIGNATURE -----\n")?;
-
         Ok(())
     }
 
@@ -130,57 +255,370 @@ impl DVSignature {
         self.content = data.to_string();
     }
 
-    pub fn sign<P: AsRef<Path>>(&mut self, private_key: P) -> Result<()> {
-        let mut key = load_private_key(private_key)?;
+    pub fn with_format(&mut self, format: DVSignFormat) {
+        self.format = format;
+    }
 
-        let data_hash = hash_string(&self.content, DVHashType::Sha512);
+    pub fn content(&self) -> &str {
+        &self.content
+    }
+
+    pub fn format(&self) -> DVSignFormat {
+        self.format
+    }
+
+    pub fn signature_key(&self) -> Result<Option<String>> {
+        self.key_of(&self.signature)
+    }
+
+    //
+    // sshsig signatures carry the key that made them
+    //
+    pub fn key_of(&self, signature: &[u8]) -> Result<Option<String>> {
+        match self.format {
+            DVSignFormat::Dver => Ok(None),
+            DVSignFormat::SshSig => {
+                let sig = SshSig::from_pem(signature)?;
+                let fingerprint = sig.public_key().fingerprint(HashAlg::Sha256);
+                Ok(Some(fingerprint.to_string()))
+            }
+        }
+    }
 
-        self.signature = key.sign(&data_hash)?;
+    //
+    // the signature first, then the cosignatures
+    //
+    pub fn signatures(&self) -> impl Iterator<Item = &[u8]> {
+        let cosignatures = self.cosignatures.iter().map(|s| s.as_slice());
+        std::iter::once(self.signature.as_slice()).chain(cosignatures)
+    }
+
+    fn make_signature(&self, key: &mut dyn Signer, namespace: &str) -> Result<Vec<u8>> {
+        let signature = match self.format {
+            DVSignFormat::Dver => {
+                let data_hash = hash_string(&self.content, DVHashType::Sha512);
+
+                info!("data hash: {}", hex::encode(&data_hash));
+
+                key.sign(namespace, &data_hash)?
+            }
+            DVSignFormat::SshSig => {
+                // sign the manifest itself so it can be checked by ssh-keygen
+                let manifest = BASE64_STANDARD.decode(&self.content)?;
+
+                let sig = key.sign_sshsig(namespace, &manifest)?;
+
+                sig.to_pem(LineEnding::LF)?.into_bytes()
+            }
+        };
 
         info!("data size: {}", self.content.len());
-        info!("data hash: {}", hex::encode(&data_hash));
-        info!("data sign: {}", hex::encode(&self.signature));
+        info!("data sign: {}", hex::encode(&signature));
 
+        Ok(signature)
+    }
+
+    pub fn sign(&mut self, key: &mut dyn Signer, namespace: &str) -> Result<()> {
+        self.signature = self.make_signature(key, namespace)?;
+        self.cosignatures.clear();
+        Ok(())
+    }
+
+    //
+    // adds a signature over the same content, the others stay valid
+    //
+    pub fn cosign(&mut self, key: &mut dyn Signer, namespace: &str) -> Result<()> {
+        let signature = self.make_signature(key, namespace)?;
+        self.cosignatures.push(signature);
         Ok(())
     }
+
+    //
+    // content is what the signature is expected to cover, the manifest rebuilt
+    // from the directory for short signatures
+    //
+    pub fn verify(&self, verifier: &dyn Verifier, namespace: &str, content: &str) -> Result<()> {
+        self.verify_with(&self.signature, verifier, namespace, content)
+    }
+
+    pub fn verify_with(
+        &self,
+        signature: &[u8],
+        verifier: &dyn Verifier,
+        namespace: &str,
+        content: &str,
+    ) -> Result<()> {
+        match self.format {
+            DVSignFormat::Dver => {
+                let data_hash = hash_string(content, DVHashType::Sha512);
+
+                info!("data hash: {}", hex::encode(&data_hash));
+
+                verifier.verify(namespace, &data_hash, signature)
+            }
+            DVSignFormat::SshSig => {
+                let manifest = BASE64_STANDARD.decode(content)?;
+                let sig = SshSig::from_pem(signature)?;
+
+                verifier.verify_sshsig(namespace, &manifest, &sig)
+            }
+        }
+    }
 }
 
-pub fn sign_directory<P: AsRef<Path>>(
+#[derive(Debug)]
+pub struct DVSignOptions {
+    pub key_type: Option<DVKeyType>,
+    pub hash_type: DVHashType,
+    pub signature_file: Option<PathBuf>,
+    pub signature_type: DVSignType,
+    pub signature_format: DVSignFormat,
+    pub exclude_list: Vec<String>,
+    pub attributes: Vec<DVAttribute>,
+    pub xattrs: Vec<String>,
+    pub follow_symlinks: bool,
+    pub special_files: DVSpecialPolicy,
+    pub subtrees: Vec<PathBuf>,
+    // unix times, the signature isn't valid outside of them
+    pub not_before: Option<u64>,
+    pub expires: Option<u64>,
+    pub jobs: usize,
+    pub cache: bool,
+    pub paranoid: bool,
+    pub namespace: String,
+    pub format: DVOutputFormat,
+}
+
+impl Default for DVSignOptions {
+    fn default() -> Self {
+        DVSignOptions {
+            key_type: None,
+            hash_type: DVHashType::Sha256,
+            signature_file: None,
+            signature_type: DVSignType::Complete,
+            signature_format: DVSignFormat::Dver,
+            exclude_list: vec![],
+            attributes: vec![],
+            xattrs: vec![],
+            follow_symlinks: false,
+            special_files: DVSpecialPolicy::Reject,
+            subtrees: vec![],
+            not_before: None,
+            expires: None,
+            jobs: 0,
+            cache: true,
+            paranoid: false,
+            namespace: DEFAULT_NAMESPACE.into(),
+            format: DVOutputFormat::Text,
+        }
+    }
+}
+
+//
+// where the signature sits in the directory, it's the only file left out of
+// the manifest
+//
+fn signature_path(directory: &Path, signature_file: &Path) -> Option<PathBuf> {
+    let parent = match signature_file.parent()? {
+        p if p.as_os_str().is_empty() => Path::new("."),
+        p => p,
+    };
+
+    let path = canonicalize(parent).ok()?.join(signature_file.file_name()?);
+
+    path.strip_prefix(directory).ok().map(PathBuf::from)
+}
+
+//
+// a signature that can never be valid is a mistake
+//
+pub fn validate_window(
+    report: &mut Report,
+    created: u64,
+    not_before: Option<u64>,
+    expires: Option<u64>,
+) -> Result<()> {
+    if let Some(not_before) = not_before {
+        report.add("Not Before", fmt_timestamp(not_before));
+    }
+
+    let expires = match expires {
+        Some(v) => v,
+        None => return Ok(()),
+    };
+
+    report.add("Expires", fmt_timestamp(expires));
+
+    if let Some(not_before) = not_before
+        && not_before >= expires
+    {
+        let err = format!(
+            "the signature would expire at {}, before it's valid at {}",
+            fmt_timestamp(expires),
+            fmt_timestamp(not_before)
+        );
+        return Err(Error::InvalidArgument(err));
+    }
+
+    if expires <= created {
+        let err = format!(
+            "the signature would have expired at {}, it's {}",
+            fmt_timestamp(expires),
+            fmt_timestamp(created)
+        );
+        return Err(Error::InvalidArgument(err));
+    }
+
+    Ok(())
+}
+
+fn sign<P: AsRef<Path>>(
+    report: &mut Report,
     directory: P,
     private_key: String,
-    hash_type: DVHashType,
-    output_sig_file: Option<P>,
-    signature_type: DVSignType,
-    exclude_list: Vec<String>,
+    opts: &DVSignOptions,
 ) -> Result<()> {
     let directory = canonicalize(directory)?;
 
-    let out_file = match &output_sig_file {
-        Some(v) => v.as_ref(),
-        None => &directory.join(DEFAULT_SIGN_FILE_NAME),
+    let out_file = match &opts.signature_file {
+        Some(v) => v,
+        None => &default_signature_file(&directory),
     };
 
-    println!("Signing:");
-    printkv("Directory", directory.display());
-    printkv("Private Key", &private_key);
-    printkv("Hash Type", hash_type);
-    printkv("Signature File", out_file.display());
-    printkv("Signature Type", &signature_type);
-    printkv("Exclude", format!("{:?}", exclude_list));
+    report.add("Directory", directory.display());
+
+    let archive = archive_type(&directory);
+
+    if let Some(archive_type) = archive {
+        report.add("Archive", archive_type);
+    }
+    report.add("Private Key", &private_key);
+    report.add("Hash Type", opts.hash_type);
+    report.add("Signature File", out_file.display());
+    report.add("Signature Type", opts.signature_type);
+    report.add("Signature Format", opts.signature_format);
+    report.add("Namespace", &opts.namespace);
+    report.add_list("Exclude", &opts.exclude_list);
+
+    let mut attributes = opts.attributes.clone();
+    attributes.sort();
+    attributes.dedup();
+
+    report.add_list("Attributes", &attributes);
+    report.add_list("Xattrs", &opts.xattrs);
+    report.add("Symlinks", fmt_symlinks(opts.follow_symlinks));
+    report.add("Special Files", opts.special_files);
+
+    let mut subtrees = Vec::new();
+
+    // each of them carries its own signature, made beforehand
+    for subtree in &opts.subtrees {
+        let subtree = normalize_path(subtree)?;
+
+        if !directory.join(&subtree).is_dir() {
+            let err = format!("{:?} isn't a subdirectory", subtree);
+            return Err(Error::InvalidPath(err));
+        }
+
+        subtrees.push(subtree);
+    }
+
+    subtrees.sort();
+    subtrees.dedup();
+
+    let fmt_subtrees: Vec<_> = subtrees.iter().map(|s| s.display()).collect();
+    report.add_list("Subtrees", &fmt_subtrees);
 
     if out_file.exists() {
         warn!("{:?} already exists", out_file);
     }
 
-    let walker = Walker::new(&directory, hash_type)?;
+    let mut key = load_private_key(private_key, opts.key_type)?;
+
+    let created = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+
+    validate_window(report, created, opts.not_before, opts.expires)?;
+
+    let header = WalkerHeader {
+        hash_type: Some(opts.hash_type),
+        created: Some(created),
+        not_before: opts.not_before,
+        expires: opts.expires,
+        tool_version: Some(TOOL_VERSION.into()),
+        signer: key.fingerprint(),
+        exclude: opts.exclude_list.clone(),
+        attributes,
+        xattrs: opts.xattrs.clone(),
+        follow_symlinks: opts.follow_symlinks,
+        special_files: opts.special_files,
+        signature: signature_path(&directory, out_file),
+        subtrees,
+        ..Default::default()
+    };
+
+    if let Some(fingerprint) = &header.signer {
+        report.add("Fingerprint", fingerprint);
+    }
+
+    let mut walker = Walker::with_header(header);
+    walker.jobs = opts.jobs;
+
+    // archives are read in one pass, there is nothing to reuse
+    let cache_file = match opts.cache && archive.is_none() {
+        true => HashCache::default_file(&directory),
+        false => None,
+    };
+
+    if let Some(cache_file) = &cache_file {
+        report.add("Cache", cache_file.display());
+        walker.cache = Some(HashCache::load(cache_file, opts.paranoid)?);
+    }
+
+    walker.walk(&directory, opts.hash_type)?;
+
+    // losing the cache only costs time
+    if let Some(cache) = &walker.cache
+        && let Err(e) = cache.save()
+    {
+        warn!("unable to save the hash cache: {e}");
+    }
+
+    if let Some(root_hash) = walker.root_hash() {
+        report.add("Root Hash", hex::encode(root_hash));
+    }
+
+    //
+    // short signatures only keep what's needed to rebuild the tree from the
+    // directory at verification time, and its root hash to compare it with
+    //
+    let content = match opts.signature_type {
+        DVSignType::Complete => walker.encode()?,
+        DVSignType::Short => walker.encode_header()?,
+    };
 
     let mut s = DVSignature::new();
 
-    s.with_content(&walker.encode()?);
-    s.sign(private_key)?;
-    s.to_file(out_file, signature_type)?;
+    s.with_format(opts.signature_format);
+    s.with_content(&content);
+    s.sign(key.as_mut(), &opts.namespace)?;
+
+    s.to_file(out_file)?;
 
-    printkv("File Size", fmt_file_size(out_file));
+    report.add("File Size", fmt_file_size(out_file));
 
     Ok(())
 }
+
+pub fn sign_directory<P: AsRef<Path>>(
+    directory: P,
+    private_key: String,
+    opts: &DVSignOptions,
+) -> Result<()> {
+    let mut report = Report::new(opts.format, "Signing");
+
+    let ret = sign(&mut report, directory, private_key, opts);
+
+    report.finish(&ret);
+
+    ret
+}
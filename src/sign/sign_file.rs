@@ -0,0 +1,223 @@
+use std::{
+    ffi::OsString,
+    fs::File,
+    io,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use base64::{prelude::BASE64_STANDARD, Engine};
+use log::warn;
+use serde_derive::{Deserialize, Serialize};
+
+use crate::{
+    common::{
+        fmt::fmt_file_size,
+        hash::{hash_reader, DVHashType},
+        r#const::{DEFAULT_NAMESPACE, DEFAULT_SIGN_FILE_NAME, STDIN_PATH, TOOL_VERSION},
+        report::{DVOutputFormat, Report},
+        serializer::{hex_deserializer, hex_serializer},
+    },
+    error::{Error, Result},
+    key::{key_type::DVKeyType, keys::load_private_key},
+    sign::sign_dir::{validate_window, DVSignFormat, DVSignature},
+};
+
+// 2: not before and expiry times
+const CUR_FILE_FORMAT_VER: u8 = 2;
+
+//
+// what a file signature covers, the content is only known through its hash.
+// The name isn't part of it, the file can be renamed
+//
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DVFileManifest {
+    pub version: u8,
+    pub hash_type: DVHashType,
+    #[serde(
+        serialize_with = "hex_serializer",
+        deserialize_with = "hex_deserializer"
+    )]
+    pub hash: Vec<u8>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created: Option<u64>,
+    // the signature is only valid in between, unix times
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub not_before: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_version: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signer: Option<String>,
+}
+
+impl DVFileManifest {
+    pub fn encode(&self) -> Result<String> {
+        let json_string = serde_json::to_string(self)?;
+        Ok(BASE64_STANDARD.encode(json_string))
+    }
+
+    pub fn decode(data: &str) -> Result<DVFileManifest> {
+        let json_data = BASE64_STANDARD.decode(data)?;
+
+        let manifest: DVFileManifest = match serde_json::from_slice(&json_data) {
+            Ok(v) => v,
+            Err(_) => return Err(Error::InvalidArgument("not a file signature".into())),
+        };
+
+        if manifest.version > CUR_FILE_FORMAT_VER {
+            return Err(Error::UnsupportedFormatVersion(manifest.version));
+        }
+
+        Ok(manifest)
+    }
+}
+
+//
+// "-" reads stdin, a stream is hashed as it comes
+//
+pub fn hash_input<P: AsRef<Path>>(input: P, hash_type: DVHashType) -> Result<Vec<u8>> {
+    let input = input.as_ref();
+
+    if input == Path::new(STDIN_PATH) {
+        return hash_reader(&mut io::stdin().lock(), 0, hash_type);
+    }
+
+    let mut fd = File::open(input)?;
+    let metadata = fd.metadata()?;
+
+    if !metadata.is_file() {
+        let err = format!("{:?} isn't a file", input);
+        return Err(Error::InvalidPath(err));
+    }
+
+    hash_reader(&mut fd, metadata.len(), hash_type)
+}
+
+//
+// next to the file, there is nowhere to put the signature of a stream
+//
+pub fn default_file_signature<P: AsRef<Path>>(input: P) -> Result<PathBuf> {
+    let input = input.as_ref();
+
+    if input == Path::new(STDIN_PATH) {
+        let err = "the signature of stdin needs a signature file".to_string();
+        return Err(Error::InvalidArgument(err));
+    }
+
+    let mut name = OsString::from(input.as_os_str());
+    name.push(".");
+    name.push(DEFAULT_SIGN_FILE_NAME);
+    Ok(name.into())
+}
+
+pub fn fmt_input<P: AsRef<Path>>(input: P) -> String {
+    match input.as_ref() == Path::new(STDIN_PATH) {
+        true => "stdin".into(),
+        false => input.as_ref().display().to_string(),
+    }
+}
+
+#[derive(Debug)]
+pub struct DVSignFileOptions {
+    pub key_type: Option<DVKeyType>,
+    pub hash_type: DVHashType,
+    pub signature_file: Option<PathBuf>,
+    pub signature_format: DVSignFormat,
+    // unix times, the signature isn't valid outside of them
+    pub not_before: Option<u64>,
+    pub expires: Option<u64>,
+    pub namespace: String,
+    pub format: DVOutputFormat,
+}
+
+impl Default for DVSignFileOptions {
+    fn default() -> Self {
+        DVSignFileOptions {
+            key_type: None,
+            hash_type: DVHashType::Sha256,
+            signature_file: None,
+            signature_format: DVSignFormat::Dver,
+            not_before: None,
+            expires: None,
+            namespace: DEFAULT_NAMESPACE.into(),
+            format: DVOutputFormat::Text,
+        }
+    }
+}
+
+fn sign<P: AsRef<Path>>(
+    report: &mut Report,
+    input: P,
+    private_key: String,
+    opts: &DVSignFileOptions,
+) -> Result<()> {
+    let input = input.as_ref();
+
+    let out_file = match &opts.signature_file {
+        Some(v) => v,
+        None => &default_file_signature(input)?,
+    };
+
+    report.add("File", fmt_input(input));
+    report.add("Private Key", &private_key);
+    report.add("Hash Type", opts.hash_type);
+    report.add("Signature File", out_file.display());
+    report.add("Signature Format", opts.signature_format);
+    report.add("Namespace", &opts.namespace);
+
+    if out_file.exists() {
+        warn!("{:?} already exists", out_file);
+    }
+
+    // the key first, a stream can only be read once
+    let mut key = load_private_key(private_key, opts.key_type)?;
+
+    if let Some(fingerprint) = key.fingerprint() {
+        report.add("Fingerprint", fingerprint);
+    }
+
+    let created = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+
+    validate_window(report, created, opts.not_before, opts.expires)?;
+
+    let manifest = DVFileManifest {
+        version: CUR_FILE_FORMAT_VER,
+        hash_type: opts.hash_type,
+        hash: hash_input(input, opts.hash_type)?,
+        created: Some(created),
+        not_before: opts.not_before,
+        expires: opts.expires,
+        tool_version: Some(TOOL_VERSION.into()),
+        signer: key.fingerprint(),
+    };
+
+    report.add("Hash", hex::encode(&manifest.hash));
+
+    let mut s = DVSignature::new();
+
+    s.with_format(opts.signature_format);
+    s.with_content(&manifest.encode()?);
+    s.sign(key.as_mut(), &opts.namespace)?;
+
+    s.to_file(out_file)?;
+
+    report.add("File Size", fmt_file_size(out_file));
+
+    Ok(())
+}
+
+pub fn sign_file<P: AsRef<Path>>(
+    input: P,
+    private_key: String,
+    opts: &DVSignFileOptions,
+) -> Result<()> {
+    let mut report = Report::new(opts.format, "Signing");
+
+    let ret = sign(&mut report, input, private_key, opts);
+
+    report.finish(&ret);
+
+    ret
+}
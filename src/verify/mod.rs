@@ -1 +1,2 @@
 pub mod verify_dir;
+pub mod verify_file;
@@ -1,61 +1,1584 @@
-use std::{fs::canonicalize, path::Path};
+use std::{
+    fs::canonicalize,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
 
-use log::info;
+use log::{info, warn};
 
 use crate::{
     common::{
-        fmt::printkv,
-        hash::{hash_string, DVHashType},
-        r#const::DEFAULT_SIGN_FILE_NAME,
+        fmt::{fmt_symlinks, fmt_timestamp},
+        hash::DVHashType,
+        r#const::{DEFAULT_NAMESPACE, LEGACY_NAMESPACES},
+        report::{DVOutputFormat, Report},
+    },
+    directory::{
+        archive::{archive_type, default_signature_file},
+        attributes::DVAttribute,
+        diff::{WalkerChange, WalkerChangeKind},
+        walker::{normalize_path, Walker, WalkerHeader},
+    },
+    error::{Error, Result},
+    key::{
+        key_type::DVKeyType,
+        keys::{load_trusted_keys, TrustedKey},
     },
-    directory::walker::Walker,
-    error::Result,
-    key::keys::load_public_key,
     sign::sign_dir::DVSignature,
 };
 
-pub fn verify_directory<P: AsRef<Path>>(
+#[derive(Debug, Clone)]
+pub struct DVVerifyOptions {
+    pub key_type: Option<DVKeyType>,
+    pub allowed_signers: Option<PathBuf>,
+    pub hash_type: Option<DVHashType>,
+    pub signature_file: Option<PathBuf>,
+    pub path: Option<PathBuf>,
+    pub jobs: usize,
+    pub enforce: Option<Vec<DVAttribute>>,
+    pub subtree_keys: Vec<DVSubtreeKey>,
+    // more trusted keys, --threshold of them have to have signed
+    pub public_keys: Vec<String>,
+    pub threshold: usize,
+    // unix time the signature is verified at, now by default
+    pub at: Option<u64>,
+    pub namespace: String,
+    pub format: DVOutputFormat,
+}
+
+impl Default for DVVerifyOptions {
+    fn default() -> Self {
+        DVVerifyOptions {
+            key_type: None,
+            allowed_signers: None,
+            hash_type: None,
+            signature_file: None,
+            path: None,
+            jobs: 0,
+            enforce: None,
+            subtree_keys: vec![],
+            public_keys: vec![],
+            threshold: 1,
+            at: None,
+            namespace: DEFAULT_NAMESPACE.into(),
+            format: DVOutputFormat::Text,
+        }
+    }
+}
+
+//
+// tries every trusted key allowed to sign in the namespace, sshsig signatures
+// only the ones with the key they carry. Signatures made by older releases
+// used a different namespace, they're only accepted when the caller didn't ask
+// for a specific one. With a threshold, that many different keys have to have
+// signed, each signature and each key counting once, whatever file or
+// principals it came from. The keys have to be valid at verified_at, the
+// signing time is whatever the signer wrote and proves nothing
+//
+pub fn verify_signature(
+    report: &mut Report,
+    s: &DVSignature,
+    keys: &[TrustedKey],
+    namespace: &str,
+    content: &str,
+    threshold: usize,
+    verified_at: u64,
+) -> Result<()> {
+    if keys.is_empty() {
+        warn!("no trusted key");
+        report.add("Namespace", namespace);
+        return Err(Error::VerificationFailure);
+    }
+
+    let mut identities: Vec<&str> = keys.iter().map(|k| k.identity()).collect();
+    identities.sort_unstable();
+    identities.dedup();
+
+    if threshold == 0 || threshold > identities.len() {
+        let err = format!(
+            "a threshold of {threshold} with {} trusted keys",
+            identities.len()
+        );
+        return Err(Error::InvalidArgument(err));
+    }
+
+    // plain gpg takes any key in the keyring, it could count as any signer
+    if threshold > 1
+        && let Some(key) = keys.iter().find(|k| k.fingerprint.is_none())
+    {
+        let err = format!(
+            "{} isn't pinned to a key, it can't count towards a threshold",
+            key.name
+        );
+        return Err(Error::InvalidArgument(err));
+    }
+
+    let mut namespaces = vec![namespace];
+
+    if namespace == DEFAULT_NAMESPACE {
+        namespaces.extend(LEGACY_NAMESPACES);
+    }
+
+    let mut ret = Err(Error::VerificationFailure);
+    // indexes of the keys that signed, one per key identity
+    let mut signers: Vec<usize> = Vec::new();
+
+    for (n, signature) in s.signatures().enumerate() {
+        // no other trusted key can have made an sshsig signature
+        let signed_by = s.key_of(signature)?;
+
+        if let Some(signed_by) = &signed_by
+            && !keys
+                .iter()
+                .any(|k| k.fingerprint.as_ref() == Some(signed_by))
+        {
+            warn!("{signed_by} isn't a trusted key");
+            continue;
+        }
+
+        'signature: for ns in &namespaces {
+            for (i, key) in keys.iter().enumerate() {
+                if signers
+                    .iter()
+                    .any(|j| keys[*j].identity() == key.identity())
+                {
+                    continue;
+                }
+
+                if signed_by.is_some() && key.fingerprint != signed_by {
+                    continue;
+                }
+
+                if !key.allows(ns, verified_at) {
+                    info!("key {i} isn't allowed to sign in {ns:?}");
+                    continue;
+                }
+
+                match s.verify_with(signature, key.verifier.as_ref(), ns, content) {
+                    Ok(_) => {
+                        if signers.is_empty() {
+                            match *ns == namespace {
+                                true => report.add("Namespace", ns),
+                                false => report.add("Namespace", format!("{ns} (legacy)")),
+                            }
+
+                            if let Some(principal) = key.principal() {
+                                report.add("Principal", principal);
+                            }
+                        }
+
+                        if *ns != namespace {
+                            warn!("signed with the legacy {ns:?} namespace");
+                        }
+
+                        signers.push(i);
+                        break 'signature;
+                    }
+                    // keep the first error, it's the most relevant one
+                    Err(e) if *ns == namespace && i == 0 && n == 0 => ret = Err(e),
+                    Err(_) => {}
+                }
+            }
+        }
+    }
+
+    if signers.is_empty() {
+        report.add("Namespace", namespace);
+        return ret;
+    }
+
+    if threshold > 1 || s.signatures().count() > 1 {
+        let names: Vec<&str> = signers.iter().map(|i| keys[*i].name.as_str()).collect();
+        report.add_list("Signers", &names);
+        report.add(
+            "Valid Signatures",
+            format!("{} of {}", signers.len(), threshold),
+        );
+    }
+
+    match signers.len() >= threshold {
+        true => Ok(()),
+        false => Err(Error::VerificationFailure),
+    }
+}
+
+//
+// now, unless the user wants to know how things stood at some other time
+//
+pub fn verification_time(at: Option<u64>) -> Result<u64> {
+    match at {
+        Some(at) => Ok(at),
+        None => Ok(SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs()),
+    }
+}
+
+//
+// the window is part of the signed manifest, it's only reported and looked at
+// once the signature checks out. at is --at, now by default
+//
+pub fn check_window(
+    report: &mut Report,
+    window: (Option<u64>, Option<u64>),
+    at: Option<u64>,
+) -> Result<()> {
+    let (not_before, expires) = window;
+
+    if let Some(not_before) = not_before {
+        report.add("Not Before", fmt_timestamp(not_before));
+    }
+
+    if let Some(expires) = expires {
+        report.add("Expires", fmt_timestamp(expires));
+    }
+
+    if let Some(at) = at {
+        report.add("Verified At", fmt_timestamp(at));
+    }
+
+    let now = verification_time(at)?;
+
+    if let Some(not_before) = not_before
+        && now < not_before
+    {
+        return Err(Error::SignatureNotYetValid);
+    }
+
+    if let Some(expires) = expires
+        && now >= expires
+    {
+        return Err(Error::SignatureExpired);
+    }
+
+    Ok(())
+}
+
+//
+// short signatures only carry the manifest header and the root hash. The
+// signature covers them, the tree is rebuilt from the directory and only
+// has to have the same root hash. Older ones signed the rebuilt manifest,
+// a modified directory can't be told from an invalid signature
+//
+fn verify_rebuilt<P: AsRef<Path>>(
+    report: &mut Report,
     directory: P,
-    public_key: String,
+    s: &DVSignature,
+    mut walker: Walker,
     hash_type: DVHashType,
-    signature_file: Option<P>,
-) -> Result<()> {
+    keys: &[TrustedKey],
+    opts: &DVVerifyOptions,
+) -> Result<Vec<WalkerChange>> {
+    let (threshold, verified_at) = (opts.threshold, verification_time(opts.at)?);
+
+    let signed_hash = walker.root_hash().map(|h| h.to_vec());
+
+    if signed_hash.is_some() {
+        verify_signature(
+            report,
+            s,
+            keys,
+            &opts.namespace,
+            s.content(),
+            threshold,
+            verified_at,
+        )?;
+    }
+
+    walker.walk(&directory, hash_type)?;
+
+    let dir_data = walker.encode()?;
+
+    info!("data len: {}", dir_data.len());
+    info!("data sign: {}", hex::encode(&s.signature));
+
+    match signed_hash {
+        Some(hash) if walker.root_hash() != Some(hash.as_slice()) => Err(Error::DirectoryModified),
+        Some(_) => Ok(vec![]),
+        None => {
+            verify_signature(
+                report,
+                s,
+                keys,
+                &opts.namespace,
+                &dir_data,
+                threshold,
+                verified_at,
+            )?;
+            Ok(vec![])
+        }
+    }
+}
+
+//
+// the attributes enforced are the ones recorded, unless the user asked for
+// fewer. Returns true when the manifest had to be trimmed
+//
+fn enforce_attributes(
+    report: &mut Report,
+    signed: &mut Walker,
+    hash_type: DVHashType,
+    enforce: Option<&[DVAttribute]>,
+) -> Result<bool> {
+    let recorded = signed.header.attributes.clone();
+
+    let enforce = match enforce {
+        Some(v) => v,
+        None => &recorded,
+    };
+
+    for attribute in enforce {
+        if !recorded.contains(attribute) {
+            warn!("{attribute} wasn't recorded when signing");
+        }
+    }
+
+    let enforced: Vec<DVAttribute> = recorded
+        .iter()
+        .filter(|a| enforce.contains(a))
+        .copied()
+        .collect();
+
+    report.add_list("Enforce", &enforced);
+
+    if enforced == recorded {
+        return Ok(false);
+    }
+
+    signed.retain_attributes(&enforced, hash_type)?;
+
+    Ok(true)
+}
+
+//
+// complete signatures carry the manifest, authenticate it first then compare
+// it with the directory so we can tell what changed
+//
+fn verify_manifest<P: AsRef<Path>>(
+    report: &mut Report,
+    directory: P,
+    s: &DVSignature,
+    mut signed: Walker,
+    hash_type: DVHashType,
+    keys: &[TrustedKey],
+    opts: &DVVerifyOptions,
+) -> Result<Vec<WalkerChange>> {
+    info!("data len: {}", s.content().len());
+    info!("data sign: {}", hex::encode(&s.signature));
+
+    let (threshold, verified_at) = (opts.threshold, verification_time(opts.at)?);
+    verify_signature(
+        report,
+        s,
+        keys,
+        &opts.namespace,
+        s.content(),
+        threshold,
+        verified_at,
+    )?;
+
+    // the directory hashes have to agree with the entries they cover
+    signed.check_hash(hash_type)?;
+
+    let trimmed = enforce_attributes(report, &mut signed, hash_type, opts.enforce.as_deref())?;
+
+    let mut current = Walker::with_header(signed.header.clone());
+    current.jobs = signed.jobs;
+    current.walk(&directory, hash_type)?;
+
+    let changes = signed.diff(&current);
+
+    let expected = match trimmed {
+        true => signed.encode()?,
+        false => s.content().to_string(),
+    };
+
+    //
+    // the diff is only a report, what matters is the manifest itself
+    //
+    if changes.is_empty() && current.encode()? != expected {
+        return Err(Error::DirectoryModified);
+    }
+
+    Ok(changes)
+}
+
+//
+// only a single entry of the manifest is compared with the directory, the
+// rest of the tree isn't read. The root hash still has to agree with the
+// whole manifest
+//
+fn verify_path<P: AsRef<Path>>(
+    report: &mut Report,
+    directory: P,
+    s: &DVSignature,
+    mut signed: Walker,
+    hash_type: DVHashType,
+    keys: &[TrustedKey],
+    opts: &DVVerifyOptions,
+) -> Result<Vec<WalkerChange>> {
+    let (threshold, verified_at) = (opts.threshold, verification_time(opts.at)?);
+    verify_signature(
+        report,
+        s,
+        keys,
+        &opts.namespace,
+        s.content(),
+        threshold,
+        verified_at,
+    )?;
+
+    signed.check_hash(hash_type)?;
+
+    enforce_attributes(report, &mut signed, hash_type, opts.enforce.as_deref())?;
+
+    match &opts.path {
+        Some(path) => signed.diff_path(directory, hash_type, path),
+        None => Err(Error::InvalidArgument("no path to verify".into())),
+    }
+}
+
+//
+// the hash type comes from the manifest, the one given by the user is only
+// checked against it. Manifests predating it fall back to the user's choice
+//
+fn select_hash_type(header: &WalkerHeader, expected: Option<DVHashType>) -> Result<DVHashType> {
+    match (header.hash_type, expected) {
+        (Some(signed), Some(expected)) if signed != expected => {
+            Err(Error::HashTypeMismatch { signed, expected })
+        }
+        (Some(signed), _) => Ok(signed),
+        (None, Some(expected)) => Ok(expected),
+        (None, None) => Ok(DVHashType::Sha256),
+    }
+}
+
+//
+// the key of a signed subtree, "vendor/lib=/etc/dver/vendor.pub"
+//
+#[derive(Debug, Clone)]
+pub struct DVSubtreeKey {
+    pub path: PathBuf,
+    pub key: String,
+}
+
+impl std::str::FromStr for DVSubtreeKey {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.split_once('=') {
+            Some((path, key)) if !path.is_empty() && !key.is_empty() => Ok(DVSubtreeKey {
+                path: normalize_path(Path::new(path))?,
+                key: key.into(),
+            }),
+            _ => Err(Error::InvalidArgument(format!(
+                "{s:?} isn't <subtree>=<key>"
+            ))),
+        }
+    }
+}
+
+//
+// who signed and when is only known once the signature checks out
+//
+fn report_signed(report: &mut Report, header: &WalkerHeader) {
+    if let Some(signer) = &header.signer {
+        report.add("Signer", signer);
+    }
+
+    if let Some(created) = header.created {
+        report.add("Signed At", fmt_timestamp(created));
+    }
+
+    if let Some(tool_version) = &header.tool_version {
+        report.add("Tool Version", tool_version);
+    }
+}
+
+//
+// a subtree is verified like a directory of its own, against its signature
+// and with its key. Its changes are reported relative to the parent
+//
+fn verify_subtree(
+    report: &mut Report,
+    directory: &Path,
+    subtree: &Path,
+    path: Option<PathBuf>,
+    opts: &DVVerifyOptions,
+) -> Result<Vec<WalkerChange>> {
+    let key = match opts.subtree_keys.iter().find(|k| k.path == subtree) {
+        Some(v) => v.key.clone(),
+        None => {
+            let err = format!("no trusted key for the {:?} subtree", subtree);
+            return Err(Error::NotFound(err));
+        }
+    };
+
+    // the keys of the subtrees it contains, relative to it
+    let subtree_keys = opts
+        .subtree_keys
+        .iter()
+        .filter_map(|k| {
+            let path = k.path.strip_prefix(subtree).ok()?;
+            (!path.as_os_str().is_empty()).then(|| DVSubtreeKey {
+                path: path.into(),
+                key: k.key.clone(),
+            })
+        })
+        .collect();
+
+    //
+    // checked the same way as the tree holding it, but only its own key
+    // vouches for it. The keys, allowed signers and threshold of the tree
+    // don't apply and it's always signed in its own dver.sig
+    //
+    let sub_opts = DVVerifyOptions {
+        key_type: opts.key_type,
+        allowed_signers: None,
+        hash_type: opts.hash_type,
+        signature_file: None,
+        path,
+        jobs: opts.jobs,
+        enforce: opts.enforce.clone(),
+        subtree_keys,
+        public_keys: vec![],
+        threshold: 1,
+        at: opts.at,
+        namespace: opts.namespace.clone(),
+        format: opts.format,
+    };
+
+    let mut section = report.section("Verifying Subtree");
+
+    let ret = verify_changes(&mut section, directory.join(subtree), Some(key), &sub_opts);
+
+    let status = match &ret {
+        Ok(changes) if changes.is_empty() => "Success",
+        _ => "Failure",
+    };
+
+    section.add("Verification", status);
+    report.add_section("Subtree Verification", section);
+
+    let prefix = match (directory.file_name(), subtree.parent()) {
+        (Some(name), Some(parent)) => Path::new(name).join(parent),
+        _ => return Err(Error::InvalidRootDirectory),
+    };
+
+    let mut changes = ret?;
+
+    for change in &mut changes {
+        change.path = prefix.join(&change.path);
+    }
+
+    Ok(changes)
+}
+
+fn needs_manifest(option: &str) -> Error {
+    Error::InvalidArgument(format!("{option} needs a complete signature"))
+}
+
+fn verify_changes<P: AsRef<Path>>(
+    report: &mut Report,
+    directory: P,
+    public_key: Option<String>,
+    opts: &DVVerifyOptions,
+) -> Result<Vec<WalkerChange>> {
     let directory = canonicalize(directory)?;
 
-    let in_file = match &signature_file {
-        Some(v) => v.as_ref(),
-        None => &directory.join(DEFAULT_SIGN_FILE_NAME),
+    let in_file = match &opts.signature_file {
+        Some(v) => v,
+        None => &default_signature_file(&directory),
     };
 
     let in_file = canonicalize(in_file)?;
 
-    println!("Verifying:");
-    printkv("Directory", directory.display());
-    printkv("Public Key", &public_key);
-    printkv("Signature File", in_file.display());
-    printkv("Hash Type", hash_type);
+    report.add("Directory", directory.display());
+
+    if let Some(archive_type) = archive_type(&directory) {
+        report.add("Archive", archive_type);
+    }
+    match &opts.public_keys[..] {
+        [] => {
+            if let Some(public_key) = &public_key {
+                report.add("Public Key", public_key);
+            }
+        }
+        more => {
+            let all: Vec<&String> = public_key.iter().chain(more).collect();
+            report.add_list("Public Keys", &all);
+        }
+    }
+
+    if let Some(allowed_signers) = &opts.allowed_signers {
+        report.add("Allowed Signers", allowed_signers.display());
+    }
+    report.add("Signature File", in_file.display());
+
+    if opts.threshold > 1 {
+        report.add("Threshold", opts.threshold);
+    }
+
+    if let Some(path) = &opts.path {
+        report.add("Path", path.display());
+    }
 
     let s = DVSignature::from_file(&in_file)?;
 
-    let walker = Walker::new(&directory, hash_type)?;
+    report.add("Signature Format", s.format());
 
-    let dir_data = walker.encode()?;
-    let dir_data_hash = hash_string(&dir_data, DVHashType::Sha512);
+    if let Some(signature_key) = s.signature_key()? {
+        report.add("Signature Key", signature_key);
+    }
 
-    info!("data len: {}", dir_data.len());
-    info!("data hash: {}", hex::encode(&dir_data_hash));
-    info!("data sign: {}", hex::encode(&s.signature));
+    //
+    // the exclusion rules come from the signed manifest, a tampered header
+    // produces a different manifest and fails the verification
+    //
+    let mut walker = match s.content().is_empty() {
+        true => Walker::with_header(WalkerHeader::default()),
+        false => Walker::decode(s.content()).map_err(Error::invalid_signature)?,
+    };
+
+    walker.jobs = opts.jobs;
+
+    let header = &walker.header;
+
+    let hash_type = select_hash_type(header, opts.hash_type)?;
+
+    report.add("Hash Type", hash_type);
+    report.add("Format Version", header.version);
+
+    if let Some(root_hash) = walker.root_hash() {
+        report.add("Root Hash", hex::encode(root_hash));
+    }
+
+    report.add_list("Exclude", &header.exclude);
+    report.add_list("Attributes", &header.attributes);
+    report.add_list("Xattrs", &header.xattrs);
+    report.add("Symlinks", fmt_symlinks(header.follow_symlinks));
+    report.add("Special Files", header.special_files);
+
+    let signed = header.clone();
+    let subtrees = header.subtrees.clone();
+    let window = (header.not_before, header.expires);
+
+    let fmt_subtrees: Vec<_> = subtrees.iter().map(|s| s.display()).collect();
+    report.add_list("Subtrees", &fmt_subtrees);
+
+    let nested: Vec<String> = walker
+        .signature_files()
+        .iter()
+        .map(|p| p.display().to_string())
+        .collect();
+
+    if !nested.is_empty() {
+        report.add_list("Nested Signatures", &nested);
+    }
+
+    let public_keys: Vec<PathBuf> = public_key
+        .iter()
+        .chain(&opts.public_keys)
+        .map(PathBuf::from)
+        .collect();
+    let keys = load_trusted_keys(&public_keys, opts.key_type, opts.allowed_signers.as_ref())?;
+
+    let path = opts.path.as_deref().map(normalize_path).transpose()?;
+
+    // the tree only records the root hash of a subtree the path is in
+    let scoped = DVVerifyOptions {
+        path: match &path {
+            Some(p) => subtrees
+                .iter()
+                .find(|s| p.starts_with(s))
+                .or(Some(p))
+                .cloned(),
+            None => None,
+        },
+        ..opts.clone()
+    };
+
+    let ret = match (walker.is_complete(), &opts.path) {
+        (true, Some(_)) => verify_path(report, &directory, &s, walker, hash_type, &keys, &scoped),
+        (true, None) => verify_manifest(report, &directory, &s, walker, hash_type, &keys, opts),
+        // without the manifest there is nothing to compare a single entry with
+        (false, Some(_)) => Err(needs_manifest("--path")),
+        (false, None) if opts.enforce.is_some() => Err(needs_manifest("--enforce")),
+        (false, None) => verify_rebuilt(report, &directory, &s, walker, hash_type, &keys, opts),
+    };
+
+    let mut changes = ret?;
+
+    report_signed(report, &signed);
+
+    check_window(report, window, opts.at)?;
 
-    let verifier = load_public_key(public_key)?;
+    //
+    // the subtrees only matter once the tree holding them checks out. With a
+    // path, only the subtree holding it or the ones below it
+    //
+    for subtree in &subtrees {
+        let sub_path = match &path {
+            Some(p) if p.starts_with(subtree) => {
+                let sub_path = p.strip_prefix(subtree)?;
+                (!sub_path.as_os_str().is_empty()).then(|| sub_path.to_path_buf())
+            }
+            Some(p) if !subtree.starts_with(p) => continue,
+            _ => None,
+        };
 
-    let ret = verifier.verify(&dir_data_hash, &s.signature);
+        changes.extend(verify_subtree(report, &directory, subtree, sub_path, opts)?);
+    }
 
-    let status = match ret {
-        Ok(_) => "Success",
-        Err(_) => "Failure",
+    Ok(changes)
+}
+
+fn verify<P: AsRef<Path>>(
+    report: &mut Report,
+    directory: P,
+    public_key: Option<String>,
+    opts: &DVVerifyOptions,
+) -> Result<()> {
+    let ret = verify_changes(report, directory, public_key, opts);
+
+    let status = match &ret {
+        Ok(changes) if changes.is_empty() => "Success",
+        _ => "Failure",
     };
 
-    printkv("Verification", status);
+    report.add("Verification", status);
+
+    let changes = ret?;
+
+    report.add_items("Changes", &changes, |c| c.kind.to_string())?;
+
+    match changes
+        .iter()
+        .all(|c| c.kind == WalkerChangeKind::Permissions)
+    {
+        _ if changes.is_empty() => Ok(()),
+        true => Err(Error::PermissionsModified),
+        false => Err(Error::DirectoryModified),
+    }
+}
+
+pub fn verify_directory<P: AsRef<Path>>(
+    directory: P,
+    public_key: Option<String>,
+    opts: &DVVerifyOptions,
+) -> Result<()> {
+    let mut report = Report::new(opts.format, "Verifying");
+
+    let ret = verify(&mut report, directory, public_key, opts);
+
+    report.finish(&ret);
 
     ret
 }
+
+#[cfg(test)]
+mod tests {
+
+    use std::{
+        fs,
+        io::Write,
+        os::unix::fs::PermissionsExt,
+        process::{Command, Stdio},
+    };
+
+    use base64::{prelude::BASE64_STANDARD, Engine};
+    use rand::{seq::SliceRandom, thread_rng};
+
+    use crate::{
+        common::{
+            r#const::DEFAULT_SIGN_FILE_NAME,
+            testing::{
+                generate_test_pgp_keys, signed_test_tree, write_random_test_keys, write_test_keys,
+                write_test_tar, write_test_tree,
+            },
+        },
+        error::EXIT_SIGNATURE_INVALID,
+        key::keys::load_private_key,
+        sign::{
+            cosign::{cosign_signature, DVCosignOptions},
+            sign_dir::{sign_directory, DVSignFormat, DVSignOptions, DVSignType},
+        },
+    };
+
+    use super::*;
+
+    fn sign_verify(signature_type: DVSignType, signature_format: DVSignFormat) {
+        let key_dir = tempfile::tempdir().unwrap();
+        let (private_key, public_key) = write_test_keys(&key_dir);
+
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let product = tmp_dir.path().join("product");
+        write_test_tree(&product);
+        fs::write(product.join("server.pyc"), "compiled").unwrap();
+
+        let private_key = private_key.display().to_string();
+        let public_key = Some(public_key.display().to_string());
+
+        let sign_opts = DVSignOptions {
+            signature_type,
+            signature_format,
+            exclude_list: vec!["*.pyc".into()],
+            ..Default::default()
+        };
+
+        sign_directory(&product, private_key, &sign_opts).unwrap();
+
+        // excluded files can change without breaking the signature
+        fs::write(product.join("server.pyc"), "recompiled").unwrap();
+        fs::write(product.join("www").join("app.pyc"), "new").unwrap();
+
+        let opts = DVVerifyOptions::default();
+        verify_directory(&product, public_key.clone(), &opts).unwrap();
+
+        // the namespace is part of what's signed
+        let other = DVVerifyOptions {
+            namespace: "other@example.com".into(),
+            ..Default::default()
+        };
+        let ret = verify_directory(&product, public_key.clone(), &other);
+        assert!(matches!(ret, Err(Error::VerificationFailure)));
+
+        fs::write(product.join("server.py"), "print('bye')\n").unwrap();
+
+        let ret = verify_directory(&product, public_key.clone(), &opts);
+        assert!(matches!(ret, Err(Error::DirectoryModified)));
+
+        // a signature that doesn't parse is as invalid as a wrong one
+        let sig_file = product.join(DEFAULT_SIGN_FILE_NAME);
+        let mut data = fs::read_to_string(&sig_file).unwrap();
+        data.insert_str(data.len() / 2, "!!");
+        fs::write(&sig_file, data).unwrap();
+
+        let ret = verify_directory(&product, public_key, &opts);
+        assert_eq!(ret.unwrap_err().exit_code(), EXIT_SIGNATURE_INVALID);
+    }
+
+    #[test]
+    fn verify_complete() {
+        sign_verify(DVSignType::Complete, DVSignFormat::Dver);
+    }
+
+    #[test]
+    fn verify_short() {
+        sign_verify(DVSignType::Short, DVSignFormat::Dver);
+    }
+
+    #[test]
+    fn verify_sshsig_complete() {
+        sign_verify(DVSignType::Complete, DVSignFormat::SshSig);
+    }
+
+    #[test]
+    fn verify_sshsig_short() {
+        sign_verify(DVSignType::Short, DVSignFormat::SshSig);
+    }
+
+    //
+    // the manifest doesn't depend on the order the file system lists the
+    // entries in, a tree created in another order still verifies
+    //
+    #[test]
+    fn verify_sorted() {
+        let key_dir = tempfile::tempdir().unwrap();
+        let (private_key, public_key) = write_test_keys(&key_dir);
+        let private_key = private_key.display().to_string();
+        let public_key = Some(public_key.display().to_string());
+
+        let names = ["b", "a", "B", "A", "_", "0", "zz", "z.txt", "é", "a b"];
+
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let product = tmp_dir.path().join("product");
+        let signature = tmp_dir.path().join("product.sig");
+
+        let create = |names: &[&str]| {
+            let _ = fs::remove_dir_all(&product);
+            for name in names {
+                let sub_dir = product.join(format!("dir_{name}"));
+                fs::create_dir_all(&sub_dir).unwrap();
+                fs::write(sub_dir.join(name), name).unwrap();
+                fs::write(product.join(name), name).unwrap();
+            }
+        };
+
+        for signature_type in [DVSignType::Complete, DVSignType::Short] {
+            create(&names);
+
+            let sign_opts = DVSignOptions {
+                signature_type,
+                signature_file: Some(signature.clone()),
+                ..Default::default()
+            };
+            sign_directory(&product, private_key.clone(), &sign_opts).unwrap();
+
+            let opts = DVVerifyOptions {
+                signature_file: Some(signature.clone()),
+                ..Default::default()
+            };
+
+            for _ in 0..4 {
+                let mut shuffled = names.to_vec();
+                shuffled.shuffle(&mut thread_rng());
+                create(&shuffled);
+
+                verify_directory(&product, public_key.clone(), &opts).unwrap();
+            }
+        }
+    }
+
+    #[test]
+    fn verify_bare_short() {
+        let key_dir = tempfile::tempdir().unwrap();
+        let (_, public_key) = write_test_keys(&key_dir);
+
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let product = tmp_dir.path().join("product");
+        write_test_tree(&product);
+
+        // what format 1 wrote for a short signature
+        let pem = pem::Pem::new("SIGNATURE ", vec![0x42; 64]);
+        fs::write(product.join(DEFAULT_SIGN_FILE_NAME), pem::encode(&pem)).unwrap();
+
+        let public_key = Some(public_key.display().to_string());
+        let ret = verify_directory(&product, public_key, &DVVerifyOptions::default());
+        assert!(matches!(ret, Err(Error::UnsupportedFormatVersion(1))));
+    }
+
+    //
+    // the sshsig format has to be usable by ssh-keygen -Y verify
+    //
+    //
+    // a signature that parses but can't be checked is as invalid as a wrong
+    // one, whatever the key type
+    //
+    #[test]
+    fn verify_corrupted_signature() {
+        let key_dir = tempfile::tempdir().unwrap();
+        let (ssh_private, ssh_public) = write_test_keys(&key_dir);
+        let mut keys = vec![(ssh_private, ssh_public)];
+
+        match generate_test_pgp_keys(&key_dir, "release") {
+            Some(v) => keys.push(v),
+            None => warn!("gpg isn't installed"),
+        }
+
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let product = tmp_dir.path().join("product");
+        write_test_tree(&product);
+
+        let sig_file = product.join(DEFAULT_SIGN_FILE_NAME);
+
+        for (private_key, public_key) in keys {
+            let private_key = private_key.display().to_string();
+            let public_key = Some(public_key.display().to_string());
+
+            sign_directory(&product, private_key, &DVSignOptions::default()).unwrap();
+
+            let mut s = DVSignature::from_file(&sig_file).unwrap();
+            s.signature.truncate(s.signature.len() / 2);
+            s.to_file(&sig_file).unwrap();
+
+            let ret = verify_directory(&product, public_key, &DVVerifyOptions::default());
+            assert!(matches!(ret, Err(Error::VerificationFailure)));
+            assert_eq!(ret.unwrap_err().exit_code(), EXIT_SIGNATURE_INVALID);
+        }
+    }
+
+    #[test]
+    fn verify_sshsig_ssh_keygen() {
+        let sign_opts = DVSignOptions {
+            signature_format: DVSignFormat::SshSig,
+            ..Default::default()
+        };
+
+        let tree = signed_test_tree(&sign_opts);
+        let sig_file = &tree.signature;
+
+        let s = DVSignature::from_file(sig_file).unwrap();
+        let manifest = BASE64_STANDARD.decode(s.content()).unwrap();
+
+        let allowed_signers = tree.dir.path().join("allowed_signers");
+        let public_key = fs::read_to_string(tree.public_key.unwrap()).unwrap();
+        fs::write(&allowed_signers, format!("joe {public_key}")).unwrap();
+
+        let child = Command::new("ssh-keygen")
+            .arg("-Y")
+            .arg("verify")
+            .arg("-f")
+            .arg(&allowed_signers)
+            .arg("-I")
+            .arg("joe")
+            .arg("-n")
+            .arg(DEFAULT_NAMESPACE)
+            .arg("-s")
+            .arg(sig_file)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn();
+
+        let mut child = match child {
+            Ok(v) => v,
+            Err(e) => {
+                warn!("unable to run ssh-keygen: {e}");
+                return;
+            }
+        };
+
+        child.stdin.take().unwrap().write_all(&manifest).unwrap();
+
+        assert!(child.wait().unwrap().success());
+    }
+
+    #[test]
+    fn verify_allowed_signers() {
+        const OTHER_KEY: &str =
+            "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIF1iu/3inePfxFL9tiaUSPbETidvVeeJbt2l3JK+TBnY";
+
+        let tree = signed_test_tree(&DVSignOptions::default());
+        let product = &tree.product;
+        let public_key = fs::read_to_string(tree.public_key.as_ref().unwrap()).unwrap();
+
+        let allowed_signers = tree.dir.path().join("allowed_signers");
+
+        let opts = DVVerifyOptions {
+            allowed_signers: Some(allowed_signers.clone()),
+            ..Default::default()
+        };
+
+        // sshsig signatures are only checked with the signers holding their key
+        for signature_format in [DVSignFormat::Dver, DVSignFormat::SshSig] {
+            let sign_opts = DVSignOptions {
+                signature_format,
+                ..Default::default()
+            };
+            sign_directory(product, tree.private_key.clone(), &sign_opts).unwrap();
+
+            let trusted = format!(
+                "ann namespaces=\"git\" {public_key}\n\
+                 bob {OTHER_KEY}\n\
+                 joe@example.com namespaces=\"dver@*\",valid-after=20200101 {public_key}\n"
+            );
+            fs::write(&allowed_signers, trusted).unwrap();
+            verify_directory(product, None, &opts).unwrap();
+
+            // wrong namespace or expired, nobody is allowed to sign
+            let untrusted = format!(
+                "ann namespaces=\"git\" {public_key}\n\
+                 bob {OTHER_KEY}\n\
+                 joe@example.com valid-before=20200101 {public_key}\n"
+            );
+            fs::write(&allowed_signers, untrusted).unwrap();
+            let ret = verify_directory(product, None, &opts);
+            assert!(matches!(ret, Err(Error::VerificationFailure)));
+
+            fs::write(&allowed_signers, format!("bob {OTHER_KEY}\n")).unwrap();
+            let ret = verify_directory(product, None, &opts);
+            assert!(matches!(ret, Err(Error::VerificationFailure)));
+        }
+
+        // the key is checked when verifying, not at the time the signer claims
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let date = time::OffsetDateTime::from_unix_timestamp(now as i64 + 2 * 86400)
+            .unwrap()
+            .date();
+        let (year, month, day) = (date.year(), date.month() as u8, date.day());
+
+        let expiring = format!("joe valid-before={year:04}{month:02}{day:02} {public_key}\n");
+        fs::write(&allowed_signers, expiring).unwrap();
+
+        verify_directory(product, None, &opts).unwrap();
+
+        let later = DVVerifyOptions {
+            at: Some(now + 30 * 86400),
+            allowed_signers: Some(allowed_signers.clone()),
+            ..Default::default()
+        };
+        let ret = verify_directory(product, None, &later);
+        assert!(matches!(ret, Err(Error::VerificationFailure)));
+    }
+
+    #[test]
+    fn verify_legacy_namespace() {
+        let key_dir = tempfile::tempdir().unwrap();
+        let (private_key, public_key) = write_test_keys(&key_dir);
+
+        let mut signer = load_private_key(private_key, None).unwrap();
+        let keys = load_trusted_keys(&[public_key], None, None::<PathBuf>).unwrap();
+        let mut report = Report::new(DVOutputFormat::Json, "Verifying");
+
+        for legacy in LEGACY_NAMESPACES {
+            let mut s = DVSignature::new();
+            s.with_content("hello");
+            s.sign(signer.as_mut(), legacy).unwrap();
+
+            let ret = verify_signature(&mut report, &s, &keys, DEFAULT_NAMESPACE, "hello", 1, 0);
+            assert!(ret.is_ok());
+
+            // legacy namespaces are only tried for the default one
+            let ret = verify_signature(&mut report, &s, &keys, "other@example.com", "hello", 1, 0);
+            assert!(ret.is_err());
+        }
+    }
+
+    #[test]
+    fn verify_path() {
+        let tree = signed_test_tree(&DVSignOptions::default());
+        let product = &tree.product;
+
+        fs::write(product.join("www").join("js").join("app.js"), "changed").unwrap();
+        fs::write(product.join("server.py"), "print('bye')\n").unwrap();
+
+        let verify_path = |path: &str| {
+            let opts = DVVerifyOptions {
+                path: Some(path.into()),
+                ..Default::default()
+            };
+            verify_directory(product, tree.public_key.clone(), &opts)
+        };
+
+        let ret = verify_path("www");
+        assert!(matches!(ret, Err(Error::DirectoryModified)));
+
+        let ret = verify_path("./server.py");
+        assert!(matches!(ret, Err(Error::DirectoryModified)));
+
+        let ret = verify_path("www/index.html");
+        assert!(ret.is_ok());
+
+        let ret = verify_path("www/missing.html");
+        assert!(matches!(ret, Err(Error::NotFound(_))));
+
+        let ret = verify_path("../product");
+        assert!(matches!(ret, Err(Error::InvalidArgument(_))));
+
+        let short = DVSignOptions {
+            signature_type: DVSignType::Short,
+            ..Default::default()
+        };
+        sign_directory(product, tree.private_key.clone(), &short).unwrap();
+
+        let ret = verify_path("www");
+        assert!(matches!(ret, Err(Error::InvalidArgument(_))));
+    }
+
+    #[test]
+    fn verify_hash_types() {
+        let tree = signed_test_tree(&DVSignOptions::default());
+        let (product, public_key) = (&tree.product, &tree.public_key);
+
+        for hash_type in [
+            DVHashType::Sha3_256,
+            DVHashType::Sha3_512,
+            DVHashType::Blake3,
+        ] {
+            let sign_opts = DVSignOptions {
+                hash_type,
+                ..Default::default()
+            };
+
+            sign_directory(product, tree.private_key.clone(), &sign_opts).unwrap();
+
+            let s = DVSignature::from_file(&tree.signature).unwrap();
+            let walker = Walker::decode(s.content()).unwrap();
+            assert_eq!(walker.header.hash_type, Some(hash_type));
+
+            verify_directory(product, public_key.clone(), &DVVerifyOptions::default()).unwrap();
+        }
+    }
+
+    #[test]
+    fn verify_attributes() {
+        let sign_opts = DVSignOptions {
+            attributes: vec![DVAttribute::Mode, DVAttribute::Uid, DVAttribute::Gid],
+            ..Default::default()
+        };
+
+        let tree = signed_test_tree(&sign_opts);
+        let (product, public_key) = (&tree.product, &tree.public_key);
+
+        let opts = DVVerifyOptions::default();
+        verify_directory(product, public_key.clone(), &opts).unwrap();
+
+        // same executable bit, only the permissions drifted
+        let server = product.join("server.py");
+        fs::set_permissions(&server, fs::Permissions::from_mode(0o600)).unwrap();
+
+        let ret = verify_directory(product, public_key.clone(), &opts);
+        assert!(matches!(ret, Err(Error::PermissionsModified)));
+
+        let ids = DVVerifyOptions {
+            enforce: Some(vec![DVAttribute::Uid, DVAttribute::Gid]),
+            ..Default::default()
+        };
+        verify_directory(product, public_key.clone(), &ids).unwrap();
+
+        fs::write(&server, "print('bye')\n").unwrap();
+        let ret = verify_directory(product, public_key.clone(), &ids);
+        assert!(matches!(ret, Err(Error::DirectoryModified)));
+
+        let short = DVSignOptions {
+            signature_type: DVSignType::Short,
+            ..sign_opts
+        };
+        sign_directory(product, tree.private_key.clone(), &short).unwrap();
+
+        verify_directory(product, public_key.clone(), &opts).unwrap();
+
+        let ret = verify_directory(product, public_key.clone(), &ids);
+        assert!(matches!(ret, Err(Error::InvalidArgument(_))));
+    }
+
+    #[test]
+    fn verify_archive() {
+        let sign_opts = DVSignOptions {
+            attributes: vec![DVAttribute::Mode, DVAttribute::Owner],
+            ..Default::default()
+        };
+
+        let tree = signed_test_tree(&sign_opts);
+        let (product, public_key) = (&tree.product, &tree.public_key);
+
+        let out = tree.dir.path().join("out");
+        fs::create_dir(&out).unwrap();
+        let archive = out.join("product.tar");
+
+        write_test_tar(product, &archive, ".");
+
+        // signed as a directory, verified as an archive
+        let dir_sig = DVVerifyOptions {
+            signature_file: Some(tree.signature.clone()),
+            ..Default::default()
+        };
+        verify_directory(&archive, public_key.clone(), &dir_sig).unwrap();
+
+        let www = DVVerifyOptions {
+            path: Some("www".into()),
+            ..dir_sig
+        };
+        verify_directory(&archive, public_key.clone(), &www).unwrap();
+
+        // and the other way around, the signature sits next to the archive
+        sign_directory(&archive, tree.private_key.clone(), &sign_opts).unwrap();
+
+        let archive_sig = DVVerifyOptions {
+            signature_file: Some(out.join("product.tar.dver.sig")),
+            ..Default::default()
+        };
+        verify_directory(product, public_key.clone(), &archive_sig).unwrap();
+
+        fs::write(product.join("server.py"), "print('bye')\n").unwrap();
+        fs::remove_file(&archive).unwrap();
+        write_test_tar(product, &archive, ".");
+
+        let ret = verify_directory(&archive, public_key.clone(), &DVVerifyOptions::default());
+        assert!(matches!(ret, Err(Error::DirectoryModified)));
+    }
+
+    #[test]
+    fn verify_nested_signatures() {
+        let tree = signed_test_tree(&DVSignOptions::default());
+        let (product, public_key) = (&tree.product, &tree.public_key);
+        fs::remove_file(&tree.signature).unwrap();
+
+        // only the signature itself is left out, wherever it is
+        let sig_file = product.join("www").join(DEFAULT_SIGN_FILE_NAME);
+
+        let sign_opts = DVSignOptions {
+            signature_file: Some(sig_file.clone()),
+            ..Default::default()
+        };
+        sign_directory(product, tree.private_key.clone(), &sign_opts).unwrap();
+
+        let s = DVSignature::from_file(&sig_file).unwrap();
+        let walker = Walker::decode(s.content()).unwrap();
+        assert_eq!(walker.header.signature, Some(PathBuf::from("www/dver.sig")));
+
+        let opts = DVVerifyOptions {
+            signature_file: Some(sig_file),
+            ..Default::default()
+        };
+        verify_directory(product, public_key.clone(), &opts).unwrap();
+
+        // a dver.sig anywhere else is content
+        fs::write(product.join(DEFAULT_SIGN_FILE_NAME), "planted").unwrap();
+        let ret = verify_directory(product, public_key.clone(), &opts);
+        assert!(matches!(ret, Err(Error::DirectoryModified)));
+
+        fs::remove_file(product.join(DEFAULT_SIGN_FILE_NAME)).unwrap();
+        let js = product.join("www").join("js").join(DEFAULT_SIGN_FILE_NAME);
+        fs::write(js, "planted").unwrap();
+        let ret = verify_directory(product, public_key.clone(), &opts);
+        assert!(matches!(ret, Err(Error::DirectoryModified)));
+    }
+
+    #[test]
+    fn verify_subtrees() {
+        let tree = signed_test_tree(&DVSignOptions::default());
+        let (product, public_key) = (&tree.product, &tree.public_key);
+
+        let (vendor_private, vendor_public) = write_random_test_keys(&tree.dir, "vendor");
+        let vendor_private = vendor_private.display().to_string();
+
+        // the vendor signs first, we only vouch for its root hash
+        let www = product.join("www");
+        let vendor_opts = DVSignOptions {
+            hash_type: DVHashType::Blake3,
+            ..Default::default()
+        };
+        sign_directory(&www, vendor_private.clone(), &vendor_opts).unwrap();
+
+        let sign_opts = DVSignOptions {
+            subtrees: vec!["./www".into()],
+            ..Default::default()
+        };
+        sign_directory(product, tree.private_key.clone(), &sign_opts).unwrap();
+
+        let s = DVSignature::from_file(&tree.signature).unwrap();
+        let walker = Walker::decode(s.content()).unwrap();
+        assert_eq!(walker.header.subtrees, [PathBuf::from("www")]);
+
+        let subtree_key = |key: &Path| DVVerifyOptions {
+            subtree_keys: vec![format!("www={}", key.display()).parse().unwrap()],
+            ..Default::default()
+        };
+
+        let opts = subtree_key(&vendor_public);
+        verify_directory(product, public_key.clone(), &opts).unwrap();
+
+        let ret = verify_directory(product, public_key.clone(), &DVVerifyOptions::default());
+        assert!(matches!(ret, Err(Error::NotFound(_))));
+
+        // a path outside of the subtree doesn't need its key
+        let server = DVVerifyOptions {
+            path: Some("server.py".into()),
+            ..Default::default()
+        };
+        verify_directory(product, public_key.clone(), &server).unwrap();
+
+        // the subtree is checked with what's asked of the tree holding it
+        let sha256 = DVVerifyOptions {
+            hash_type: Some(DVHashType::Sha256),
+            ..subtree_key(&vendor_public)
+        };
+        let ret = verify_directory(product, public_key.clone(), &sha256);
+        assert!(matches!(ret, Err(Error::HashTypeMismatch { .. })));
+
+        // our key doesn't vouch for the vendor's content
+        let ours = subtree_key(Path::new(public_key.as_ref().unwrap()));
+        let ret = verify_directory(product, public_key.clone(), &ours);
+        assert!(matches!(ret, Err(Error::VerificationFailure)));
+
+        fs::write(www.join("index.html"), "changed").unwrap();
+        fs::write(www.join("js").join("app.js"), "changed").unwrap();
+
+        let changes = |path: Option<&str>| {
+            let opts = DVVerifyOptions {
+                path: path.map(PathBuf::from),
+                ..subtree_key(&vendor_public)
+            };
+            let mut report = Report::new(DVOutputFormat::Json, "Verifying");
+            let changes = verify_changes(&mut report, product, public_key.clone(), &opts);
+            let paths: Vec<PathBuf> = changes.unwrap().into_iter().map(|c| c.path).collect();
+            paths
+        };
+
+        let all = [
+            PathBuf::from("product/www/index.html"),
+            PathBuf::from("product/www/js/app.js"),
+        ];
+        assert_eq!(changes(None), all);
+        assert_eq!(changes(Some(".")), all);
+        assert_eq!(changes(Some("www")), all);
+        assert_eq!(changes(Some("www/js")), all[1..]);
+        assert!(changes(Some("server.py")).is_empty());
+
+        // signed again by the vendor, it's no longer what we signed
+        sign_directory(&www, vendor_private, &vendor_opts).unwrap();
+        let ret = verify_directory(product, public_key.clone(), &opts);
+        assert!(matches!(ret, Err(Error::DirectoryModified)));
+
+        assert!("www".parse::<DVSubtreeKey>().is_err());
+        assert!("../www=key.pub".parse::<DVSubtreeKey>().is_err());
+    }
+
+    #[test]
+    fn verify_threshold() {
+        let tree = signed_test_tree(&DVSignOptions::default());
+        let (product, sig_file) = (&tree.product, &tree.signature);
+        let (a_private, a_public) = (&tree.private_key, &tree.public_key);
+
+        let (b_private, b_public) = write_random_test_keys(&tree.dir, "b");
+        let (_, c_public) = write_random_test_keys(&tree.dir, "c");
+        let b_public = b_public.display().to_string();
+        let c_public = c_public.display().to_string();
+
+        let verify = |public_keys: &[&String], threshold: usize| {
+            let opts = DVVerifyOptions {
+                public_keys: public_keys.iter().map(|k| k.to_string()).collect(),
+                threshold,
+                ..Default::default()
+            };
+            verify_directory(product, a_public.clone(), &opts)
+        };
+
+        // the signature there has to check out before it's signed again
+        let cosign_opts = |public_key: &String| DVCosignOptions {
+            public_keys: vec![public_key.clone()],
+            ..Default::default()
+        };
+        let a = a_public.clone().unwrap();
+
+        for signature_format in [DVSignFormat::Dver, DVSignFormat::SshSig] {
+            let sign_opts = DVSignOptions {
+                signature_format,
+                ..Default::default()
+            };
+            sign_directory(product, a_private.clone(), &sign_opts).unwrap();
+
+            let ret = verify(&[&b_public, &c_public], 2);
+            assert!(matches!(ret, Err(Error::VerificationFailure)));
+
+            let b_private = b_private.display().to_string();
+            let ret = cosign_signature(sig_file, b_private.clone(), &cosign_opts(&c_public));
+            assert!(matches!(ret, Err(Error::VerificationFailure)));
+
+            let s = DVSignature::from_file(sig_file).unwrap();
+            assert_eq!(s.signatures().count(), 1);
+
+            cosign_signature(sig_file, b_private, &cosign_opts(&a)).unwrap();
+
+            let s = DVSignature::from_file(sig_file).unwrap();
+            assert_eq!(s.signatures().count(), 2);
+
+            verify(&[], 1).unwrap();
+            verify(&[&b_public, &c_public], 2).unwrap();
+
+            // each signature counts once, whatever the number of keys
+            let ret = verify(&[&a, &c_public], 2);
+            assert!(matches!(ret, Err(Error::VerificationFailure)));
+
+            let ret = verify(&[&b_public, &c_public], 4);
+            assert!(matches!(ret, Err(Error::InvalidArgument(_))));
+        }
+
+        // each key counts once, however many times it signed or was given
+        let a_copy = tree.dir.path().join("a_copy.pub");
+        fs::copy(&a, &a_copy).unwrap();
+        let a_copy = a_copy.display().to_string();
+
+        sign_directory(product, a_private.clone(), &DVSignOptions::default()).unwrap();
+        cosign_signature(sig_file, a_private.clone(), &cosign_opts(&a)).unwrap();
+
+        let ret = verify(&[&a, &a], 2);
+        assert!(matches!(ret, Err(Error::InvalidArgument(_))));
+
+        let ret = verify(&[&a, &a_copy, &c_public], 2);
+        assert!(matches!(ret, Err(Error::VerificationFailure)));
+
+        verify(&[&a, &a_copy], 1).unwrap();
+
+        // nobody is allowed to sign
+        let allowed_signers = tree.dir.path().join("allowed_signers");
+        fs::write(&allowed_signers, "").unwrap();
+        let opts = DVVerifyOptions {
+            allowed_signers: Some(allowed_signers),
+            ..Default::default()
+        };
+        let ret = verify_directory(product, None, &opts);
+        assert!(matches!(ret, Err(Error::VerificationFailure)));
+
+        // a short signature covers the header and the root hash it keeps
+        let short = DVSignOptions {
+            signature_type: DVSignType::Short,
+            ..Default::default()
+        };
+        sign_directory(product, a_private.clone(), &short).unwrap();
+        let b_private = b_private.display().to_string();
+        cosign_signature(sig_file, b_private, &cosign_opts(&a)).unwrap();
+        verify(&[&b_public, &c_public], 2).unwrap();
+    }
+
+    #[test]
+    fn verify_window() {
+        let tree = signed_test_tree(&DVSignOptions::default());
+        let (product, sig_file) = (&tree.product, &tree.signature);
+
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+
+        let sign = |not_before: Option<u64>, expires: Option<u64>| {
+            let sign_opts = DVSignOptions {
+                not_before,
+                expires,
+                ..Default::default()
+            };
+            sign_directory(product, tree.private_key.clone(), &sign_opts)
+        };
+
+        let verify = |at: Option<u64>| {
+            let opts = DVVerifyOptions {
+                at,
+                ..Default::default()
+            };
+            verify_directory(product, tree.public_key.clone(), &opts)
+        };
+
+        let expires = now + 3600;
+        sign(None, Some(expires)).unwrap();
+
+        verify(None).unwrap();
+        verify(Some(now + 1800)).unwrap();
+
+        let ret = verify(Some(now + 7200));
+        assert!(matches!(ret, Err(Error::SignatureExpired)));
+
+        // the window is signed, pushing it back breaks the signature
+        let mut s = DVSignature::from_file(sig_file).unwrap();
+        let mut walker = Walker::decode(s.content()).unwrap();
+        walker.header.expires = Some(expires + 86400);
+        s.with_content(&walker.encode().unwrap());
+        s.to_file(sig_file).unwrap();
+
+        let ret = verify(Some(now + 7200));
+        assert!(matches!(ret, Err(Error::VerificationFailure)));
+
+        sign(Some(now + 3600), Some(now + 7200)).unwrap();
+
+        let ret = verify(None);
+        assert!(matches!(ret, Err(Error::SignatureNotYetValid)));
+        verify(Some(now + 5400)).unwrap();
+
+        let ret = sign(None, Some(now - 60));
+        assert!(matches!(ret, Err(Error::InvalidArgument(_))));
+
+        let ret = sign(Some(now + 7200), Some(now + 3600));
+        assert!(matches!(ret, Err(Error::InvalidArgument(_))));
+    }
+
+    #[test]
+    fn hash_type_selection() {
+        let mut header = WalkerHeader::default();
+
+        let hash_type = select_hash_type(&header, None).unwrap();
+        assert_eq!(hash_type, DVHashType::Sha256);
+
+        let hash_type = select_hash_type(&header, Some(DVHashType::Sha512)).unwrap();
+        assert_eq!(hash_type, DVHashType::Sha512);
+
+        header.hash_type = Some(DVHashType::Sha512);
+
+        let hash_type = select_hash_type(&header, None).unwrap();
+        assert_eq!(hash_type, DVHashType::Sha512);
+
+        let ret = select_hash_type(&header, Some(DVHashType::Sha256));
+        assert!(matches!(ret, Err(Error::HashTypeMismatch { .. })));
+    }
+}
@@ -0,0 +1,347 @@
+use std::path::{Path, PathBuf};
+
+use crate::{
+    common::{
+        fmt::fmt_timestamp,
+        hash::DVHashType,
+        r#const::DEFAULT_NAMESPACE,
+        report::{DVOutputFormat, Report},
+    },
+    error::{Error, Result},
+    key::{key_type::DVKeyType, keys::load_trusted_keys},
+    sign::{
+        sign_dir::DVSignature,
+        sign_file::{default_file_signature, fmt_input, hash_input, DVFileManifest},
+    },
+    verify::verify_dir::{check_window, verification_time, verify_signature},
+};
+
+#[derive(Debug)]
+pub struct DVVerifyFileOptions {
+    pub key_type: Option<DVKeyType>,
+    pub allowed_signers: Option<PathBuf>,
+    pub hash_type: Option<DVHashType>,
+    pub signature_file: Option<PathBuf>,
+    // more trusted keys, threshold of them have to have signed
+    pub public_keys: Vec<String>,
+    pub threshold: usize,
+    // verify as of this unix time instead of now
+    pub at: Option<u64>,
+    pub namespace: String,
+    pub format: DVOutputFormat,
+}
+
+impl Default for DVVerifyFileOptions {
+    fn default() -> Self {
+        DVVerifyFileOptions {
+            key_type: None,
+            allowed_signers: None,
+            hash_type: None,
+            signature_file: None,
+            public_keys: vec![],
+            threshold: 1,
+            at: None,
+            namespace: DEFAULT_NAMESPACE.into(),
+            format: DVOutputFormat::Text,
+        }
+    }
+}
+
+//
+// who signed and when is only known once the signature checks out
+//
+fn report_signed(report: &mut Report, manifest: &DVFileManifest) {
+    if let Some(signer) = &manifest.signer {
+        report.add("Signer", signer);
+    }
+
+    if let Some(created) = manifest.created {
+        report.add("Signed At", fmt_timestamp(created));
+    }
+
+    if let Some(tool_version) = &manifest.tool_version {
+        report.add("Tool Version", tool_version);
+    }
+}
+
+fn verify<P: AsRef<Path>>(
+    report: &mut Report,
+    input: P,
+    public_key: Option<String>,
+    opts: &DVVerifyFileOptions,
+) -> Result<()> {
+    let input = input.as_ref();
+
+    let in_file = match &opts.signature_file {
+        Some(v) => v,
+        None => &default_file_signature(input)?,
+    };
+
+    report.add("File", fmt_input(input));
+
+    match &opts.public_keys[..] {
+        [] => {
+            if let Some(public_key) = &public_key {
+                report.add("Public Key", public_key);
+            }
+        }
+        more => {
+            let all: Vec<&String> = public_key.iter().chain(more).collect();
+            report.add_list("Public Keys", &all);
+        }
+    }
+
+    if let Some(allowed_signers) = &opts.allowed_signers {
+        report.add("Allowed Signers", allowed_signers.display());
+    }
+    report.add("Signature File", in_file.display());
+
+    if opts.threshold > 1 {
+        report.add("Threshold", opts.threshold);
+    }
+
+    let s = DVSignature::from_file(in_file)?;
+
+    report.add("Signature Format", s.format());
+
+    if let Some(signature_key) = s.signature_key()? {
+        report.add("Signature Key", signature_key);
+    }
+
+    // short directory signatures have no content at all
+    if s.content().is_empty() {
+        return Err(Error::InvalidArgument("not a file signature".into()));
+    }
+
+    let manifest = DVFileManifest::decode(s.content())?;
+
+    if let Some(expected) = opts.hash_type
+        && expected != manifest.hash_type
+    {
+        let signed = manifest.hash_type;
+        return Err(Error::HashTypeMismatch { signed, expected });
+    }
+
+    report.add("Hash Type", manifest.hash_type);
+    report.add("Format Version", manifest.version);
+    report.add("Hash", hex::encode(&manifest.hash));
+
+    let public_keys: Vec<PathBuf> = public_key
+        .iter()
+        .chain(&opts.public_keys)
+        .map(PathBuf::from)
+        .collect();
+    let keys = load_trusted_keys(&public_keys, opts.key_type, opts.allowed_signers.as_ref())?;
+
+    //
+    // the signature first, a stream isn't worth reading for a hash nobody
+    // vouches for
+    //
+    // the allowed signers are checked now, or at --at
+    let verified_at = verification_time(opts.at)?;
+
+    let (content, threshold) = (s.content(), opts.threshold);
+    let ret = verify_signature(
+        report,
+        &s,
+        &keys,
+        &opts.namespace,
+        content,
+        threshold,
+        verified_at,
+    )
+    .and_then(|_| {
+        report_signed(report, &manifest);
+
+        check_window(report, (manifest.not_before, manifest.expires), opts.at)?;
+
+        match hash_input(input, manifest.hash_type)? == manifest.hash {
+            true => Ok(()),
+            false => Err(Error::FileModified),
+        }
+    });
+
+    let status = match &ret {
+        Ok(_) => "Success",
+        Err(_) => "Failure",
+    };
+
+    report.add("Verification", status);
+
+    ret
+}
+
+pub fn verify_file<P: AsRef<Path>>(
+    input: P,
+    public_key: Option<String>,
+    opts: &DVVerifyFileOptions,
+) -> Result<()> {
+    let mut report = Report::new(opts.format, "Verifying");
+
+    let ret = verify(&mut report, input, public_key, opts);
+
+    report.finish(&ret);
+
+    ret
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::{
+        fs,
+        time::{SystemTime, UNIX_EPOCH},
+    };
+
+    use crate::{
+        common::testing::{with_stdin, write_test_keys},
+        sign::{
+            sign_dir::DVSignFormat,
+            sign_file::{sign_file, DVSignFileOptions},
+        },
+    };
+
+    use super::*;
+
+    fn sign_verify(signature_format: DVSignFormat) {
+        let key_dir = tempfile::tempdir().unwrap();
+        let (private_key, public_key) = write_test_keys(&key_dir);
+
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let file = tmp_dir.path().join("product.bin");
+        fs::write(&file, "release").unwrap();
+
+        let private_key = private_key.display().to_string();
+        let public_key = Some(public_key.display().to_string());
+
+        let sign_opts = DVSignFileOptions {
+            signature_format,
+            hash_type: DVHashType::Blake3,
+            ..Default::default()
+        };
+
+        sign_file(&file, private_key, &sign_opts).unwrap();
+        assert!(tmp_dir.path().join("product.bin.dver.sig").exists());
+
+        let opts = DVVerifyFileOptions::default();
+        verify_file(&file, public_key.clone(), &opts).unwrap();
+
+        let sha256 = DVVerifyFileOptions {
+            hash_type: Some(DVHashType::Sha256),
+            ..Default::default()
+        };
+        let ret = verify_file(&file, public_key.clone(), &sha256);
+        assert!(matches!(ret, Err(Error::HashTypeMismatch { .. })));
+
+        // the namespace is part of what's signed
+        let other = DVVerifyFileOptions {
+            namespace: "other@example.com".into(),
+            ..Default::default()
+        };
+        let ret = verify_file(&file, public_key.clone(), &other);
+        assert!(matches!(ret, Err(Error::VerificationFailure)));
+
+        fs::write(&file, "tampered").unwrap();
+
+        let ret = verify_file(&file, public_key, &opts);
+        assert!(matches!(ret, Err(Error::FileModified)));
+    }
+
+    #[test]
+    fn verify_file_dver() {
+        sign_verify(DVSignFormat::Dver);
+    }
+
+    #[test]
+    fn verify_file_sshsig() {
+        sign_verify(DVSignFormat::SshSig);
+    }
+
+    #[test]
+    fn verify_file_stdin() {
+        let key_dir = tempfile::tempdir().unwrap();
+        let (private_key, public_key) = write_test_keys(&key_dir);
+
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let file = tmp_dir.path().join("product.bin");
+        fs::write(&file, "release").unwrap();
+
+        let signature_file = tmp_dir.path().join("stdin.dver.sig");
+
+        let sign_opts = DVSignFileOptions {
+            signature_file: Some(signature_file.clone()),
+            ..Default::default()
+        };
+        let private_key = private_key.display().to_string();
+        with_stdin(&file, || sign_file("-", private_key, &sign_opts)).unwrap();
+
+        let opts = DVVerifyFileOptions {
+            signature_file: Some(signature_file),
+            ..Default::default()
+        };
+        let public_key = Some(public_key.display().to_string());
+
+        // the file and the stream are the same content
+        verify_file(&file, public_key.clone(), &opts).unwrap();
+        with_stdin(&file, || verify_file("-", public_key.clone(), &opts)).unwrap();
+
+        fs::write(&file, "tampered").unwrap();
+
+        let ret = with_stdin(&file, || verify_file("-", public_key, &opts));
+        assert!(matches!(ret, Err(Error::FileModified)));
+    }
+
+    #[test]
+    fn verify_file_window() {
+        let key_dir = tempfile::tempdir().unwrap();
+        let (private_key, public_key) = write_test_keys(&key_dir);
+
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let file = tmp_dir.path().join("product.bin");
+        fs::write(&file, "release").unwrap();
+
+        let private_key = private_key.display().to_string();
+        let public_key = Some(public_key.display().to_string());
+
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+
+        let sign = |not_before: Option<u64>, expires: Option<u64>| {
+            let sign_opts = DVSignFileOptions {
+                not_before,
+                expires,
+                ..Default::default()
+            };
+            sign_file(&file, private_key.clone(), &sign_opts)
+        };
+
+        let verify = |at: Option<u64>| {
+            let opts = DVVerifyFileOptions {
+                at,
+                ..Default::default()
+            };
+            verify_file(&file, public_key.clone(), &opts)
+        };
+
+        sign(Some(now + 3600), Some(now + 7200)).unwrap();
+
+        let ret = verify(None);
+        assert!(matches!(ret, Err(Error::SignatureNotYetValid)));
+        verify(Some(now + 5400)).unwrap();
+
+        let ret = verify(Some(now + 7200));
+        assert!(matches!(ret, Err(Error::SignatureExpired)));
+
+        let ret = sign(None, Some(now - 60));
+        assert!(matches!(ret, Err(Error::InvalidArgument(_))));
+    }
+
+    #[test]
+    fn verify_file_stdin_signature() {
+        // there is no file to put the signature next to
+        let ret = sign_file("-", "key".into(), &DVSignFileOptions::default());
+        assert!(matches!(ret, Err(Error::InvalidArgument(_))));
+    }
+}